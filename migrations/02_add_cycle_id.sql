@@ -0,0 +1 @@
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS cycle_id TEXT;
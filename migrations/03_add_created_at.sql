@@ -0,0 +1,3 @@
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS transaction_stats_created_at_idx ON transaction_stats (created_at);
@@ -0,0 +1,3 @@
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS snapshot_time_ms BIGINT;
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS heap_build_time_ms BIGINT;
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS prep_time_ms BIGINT;
@@ -0,0 +1,6 @@
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS unmatched_buy_volume TEXT;
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS unmatched_sell_volume TEXT;
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS skipped_zero_amount INT;
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS skipped_self_trade INT;
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS skipped_below_notional INT;
+ALTER TABLE transaction_stats ADD COLUMN IF NOT EXISTS skipped_owner_capped INT;
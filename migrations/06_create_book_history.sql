@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS book_history (
+    snapshot_id SERIAL PRIMARY KEY,
+    sequence BIGINT NOT NULL,
+    bids JSONB NOT NULL,
+    asks JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS book_history_created_at_idx ON book_history (created_at);
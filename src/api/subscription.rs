@@ -1,8 +1,6 @@
-use crate::config::ev;
 use crate::model::OrderType;
 
-pub fn format_graphql_subscription(order_type: OrderType) -> String {
-    let limit = ev("FETCH_ORDER_LIMIT").unwrap_or_default();
+pub fn format_graphql_subscription(order_type: OrderType, limit: u32) -> String {
     let (order_type_str, order_by) = match order_type {
         OrderType::Sell => ("Sell", "asc"),
         OrderType::Buy => ("Buy", "desc"),
@@ -1,3 +1,5 @@
 pub mod env;
+pub mod settings;
 
 pub use env::ev;
+pub use settings::{Config, MatcherConfig};
@@ -0,0 +1,574 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+use fuels::types::ContractId;
+use url::Url;
+
+use crate::error::Error;
+use crate::logger::LogSinkKind;
+use crate::management::limits::IngestLimits;
+use crate::management::rate_limit::OwnerRateLimit;
+use crate::management::snapshot::CrossedBookPolicy;
+use crate::market::chunking::{ChunkBy, ChunkOrder};
+use crate::market::confirm::ConfirmationWait;
+use crate::market::crossing::{FeeSchedule, MatchSettings};
+#[cfg(feature = "fault-injection")]
+use crate::market::fault::FaultConfig;
+use crate::market::matcher::{CycleMode, MatchCall};
+use crate::market::schedule::Schedule;
+use crate::match_export::KafkaExport;
+use crate::redis_ingest::RedisIngest;
+
+/// Everything the process reads from the environment, loaded and validated
+/// once at startup.
+#[derive(Clone)]
+pub struct Config {
+    pub websocket_url: Url,
+    pub fetch_order_limit: u32,
+    /// Primary database; only required by the Postgres log sink and the
+    /// book history. Without it and a read replica, `/stats` is not served.
+    pub database_url: Option<String>,
+    /// Replica for the read-only stats endpoints; the primary when unset.
+    pub database_read_url: Option<String>,
+    pub provider_url: String,
+    pub provider_connect_timeout: Duration,
+    pub mnemonic: String,
+    pub standby_mnemonic: Option<String>,
+    pub contract_id: ContractId,
+    pub log_sink: LogSinkKind,
+    pub log_aggregate_window: Option<Duration>,
+    /// Postgres stats rows older than this are deleted; `None` keeps them.
+    pub log_retention: Option<Duration>,
+    pub log_retention_interval: Duration,
+    pub mutation_log_path: Option<String>,
+    pub book_snapshot_path: Option<String>,
+    pub book_snapshot_interval: Duration,
+    /// Snapshots older than this are ignored on startup.
+    pub book_snapshot_max_age: Duration,
+    /// Applied when the restored book's best bid is more than
+    /// `restored_cross_threshold` above its best ask.
+    pub restored_cross_policy: CrossedBookPolicy,
+    pub restored_cross_threshold: u128,
+    /// How often the book's invariants are checked; `None` disables it.
+    pub consistency_check_interval: Option<Duration>,
+    /// How often the top `book_history_depth` levels are written to
+    /// Postgres; `None` disables it.
+    pub book_history_interval: Option<Duration>,
+    pub book_history_depth: usize,
+    pub redis: Option<RedisIngest>,
+    pub kafka: Option<KafkaExport>,
+    /// OTLP collector receiving a span per cycle and per chunk; `None`
+    /// emits no spans.
+    pub otel_endpoint: Option<String>,
+    pub ingest_limits: IngestLimits,
+    pub matcher: MatcherConfig,
+}
+
+/// Knobs read by `SparkMatcher` on every cycle.
+#[derive(Debug, Clone)]
+pub struct MatcherConfig {
+    pub settings: MatchSettings,
+    pub cycle_mode: CycleMode,
+    pub chunk_by: ChunkBy,
+    pub chunk_size: Option<usize>,
+    pub chunk_order: ChunkOrder,
+    /// Caps per `match_order_many` call; chunks over either are split.
+    pub max_chunk_amount: Option<u128>,
+    pub max_chunk_ids: Option<usize>,
+    pub match_call: MatchCall,
+    pub min_book_orders: usize,
+    pub match_interval: Duration,
+    pub cycle_budget: Option<Duration>,
+    /// Pause between consecutive chunk submissions, for rate-limited RPCs.
+    pub chunk_delay: Option<Duration>,
+    /// Warn and count `stale_cycles` when cycles are further apart than this.
+    pub stale_cycle_gap: Option<Duration>,
+    /// Skip cycles, counting `feed_lag_skipped_cycles`, while the feed has
+    /// delivered no order for longer than this (or none at all yet).
+    pub max_feed_lag: Option<Duration>,
+    /// Daily UTC windows in which cycles run; `None` runs around the clock.
+    pub schedule: Option<Schedule>,
+    pub gas_balance_floor: Option<u64>,
+    pub gas_topup_amount: u64,
+    pub standby_failure_threshold: u32,
+    /// How long the primary wallet sits out after the standby took over;
+    /// `None` keeps the standby for good.
+    pub primary_cooldown: Option<Duration>,
+    /// Look every matched order up on chain before submitting and clamp the
+    /// matches to what is left. Costs one call per order.
+    pub clamp_to_onchain: bool,
+    /// Only submit match groups whose aggregating order is filled against
+    /// at least this many distinct users in the cycle.
+    pub min_counterparties: Option<usize>,
+    /// After each successful chunk, read its orders back on chain and log
+    /// fills that differ from the computed ones. One call per order.
+    pub verify_fills: bool,
+    /// Only these order ids are crossed, for exercising the submit path
+    /// against known orders on testnet; `None` crosses the whole book.
+    pub order_whitelist: Option<HashSet<String>>,
+    /// Orders booked more recently than this sit out the cycle, so quotes
+    /// that are about to be cancelled are not matched.
+    pub min_order_age: Option<Duration>,
+    /// Pause instead of submitting when a cycle matches more than this
+    /// multiple of the recent average volume.
+    pub volume_anomaly_multiple: Option<f64>,
+    pub volume_anomaly_window: usize,
+    /// Defer matches priced more than this fraction away from the average
+    /// of the last `price_band_window` matched prices.
+    pub price_band: Option<f64>,
+    pub price_band_window: usize,
+    /// Extra attempts per failed chunk before it counts as failed.
+    pub chunk_retries: u32,
+    pub retry_backoff: Duration,
+    pub retry_jitter: f64,
+    pub retry_seed: Option<u64>,
+    /// How many order ids from failed chunks are kept to be tried first in
+    /// the next cycle; 0 disables it.
+    pub retry_queue_size: usize,
+    /// Quarantine an order after it was in this many failed chunks in a row.
+    pub poison_order_threshold: Option<u32>,
+    /// Wait for each submitted chunk to be confirmed before logging it as
+    /// matched; `None` trusts the submission response.
+    pub confirmation: Option<ConfirmationWait>,
+    #[cfg(feature = "fault-injection")]
+    pub fault: Option<FaultConfig>,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, Error> {
+        let vars: HashMap<String, String> = std::env::vars().collect();
+        Self::from_map(&vars)
+    }
+
+    /// Builds the config from `KEY -> value` pairs. Every key is checked
+    /// before returning, so one error lists all missing and invalid values.
+    pub fn from_map(vars: &HashMap<String, String>) -> Result<Self, Error> {
+        let mut vars = Vars {
+            vars,
+            errors: Vec::new(),
+        };
+
+        let websocket_url = vars.required::<Url>("WEBSOCKET_URL");
+        let mnemonic = vars.required::<String>("MNEMONIC");
+        let contract_id = vars.required::<ContractId>("CONTRACT_ID");
+
+        let redis = if vars.optional("REDIS_INGEST").unwrap_or(false) {
+            let url = vars.required::<String>("REDIS_URL");
+            let stream = vars.required::<String>("REDIS_STREAM");
+            match (url, stream) {
+                (Some(url), Some(stream)) => Some(RedisIngest {
+                    url,
+                    stream,
+                    group: vars
+                        .optional("REDIS_GROUP")
+                        .unwrap_or_else(|| "spark-matcher".to_string()),
+                    consumer: vars
+                        .optional("REDIS_CONSUMER")
+                        .unwrap_or_else(|| "matcher-1".to_string()),
+                    dead_letter_stream: vars.optional("REDIS_DEAD_LETTER_STREAM"),
+                }),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        let kafka = if vars.optional("KAFKA_EXPORT").unwrap_or(false) {
+            let brokers = vars.required::<String>("KAFKA_BROKERS");
+            let topic = vars.required::<String>("KAFKA_TOPIC");
+            match (brokers, topic) {
+                (Some(brokers), Some(topic)) => Some(KafkaExport {
+                    brokers,
+                    topic,
+                    buffer: vars.optional("KAFKA_BUFFER").unwrap_or(1024),
+                }),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        let maker_rebate_bps = vars.optional("MAKER_REBATE_BPS");
+        let taker_fee_bps = vars.optional("TAKER_FEE_BPS");
+        let settings = MatchSettings {
+            max_cycle_notional: vars.optional("MAX_CYCLE_NOTIONAL"),
+            min_match_notional: vars.optional("MIN_MATCH_NOTIONAL"),
+            max_fill_per_owner: vars.optional("MAX_FILL_PER_OWNER"),
+            time_priority: vars.optional("TIME_PRIORITY").unwrap_or(false),
+            tag_priority: vars
+                .optional::<String>("TAG_PRIORITY")
+                .map(|tags| {
+                    tags.split(',')
+                        .map(str::trim)
+                        .filter(|tag| !tag.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            strict_crossing: vars.optional("STRICT_PRICE_CROSSING").unwrap_or(false),
+            max_price_levels: vars.optional("MAX_PRICE_LEVELS"),
+            fair_allocation: vars.optional("FAIR_MAKER_ALLOCATION").unwrap_or(false),
+            self_trade_prevention: vars.optional("SELF_TRADE_PREVENTION"),
+            improvement_policy: vars.optional("PRICE_IMPROVEMENT_TO").unwrap_or_default(),
+            equal_size_priority: vars.optional("EQUAL_SIZE_PRIORITY"),
+            fees: if maker_rebate_bps.is_some() || taker_fee_bps.is_some() {
+                Some(FeeSchedule {
+                    maker_rebate_bps: maker_rebate_bps.unwrap_or(0),
+                    taker_fee_bps: taker_fee_bps.unwrap_or(0),
+                })
+            } else {
+                None
+            },
+            strategy: vars.optional("MATCH_STRATEGY").unwrap_or_default(),
+        };
+
+        let matcher = MatcherConfig {
+            settings,
+            cycle_mode: vars.optional("CYCLE_MODE").unwrap_or_default(),
+            chunk_by: vars.optional("MATCH_CHUNK_BY").unwrap_or_default(),
+            chunk_size: vars.optional("MATCH_CHUNK_SIZE"),
+            chunk_order: vars.optional("MATCH_CHUNK_ORDER").unwrap_or_default(),
+            max_chunk_amount: vars.optional("MATCH_CHUNK_MAX_AMOUNT"),
+            max_chunk_ids: vars.optional("MATCH_CHUNK_MAX_IDS"),
+            match_call: vars.optional("MATCH_CALL").unwrap_or_default(),
+            min_book_orders: vars.optional("MIN_BOOK_ORDERS").unwrap_or(0),
+            match_interval: vars
+                .positive_millis("MATCH_INTERVAL_MS")
+                .unwrap_or(Duration::from_secs(1)),
+            cycle_budget: vars.millis("CYCLE_BUDGET_MS"),
+            chunk_delay: vars.millis("CHUNK_DELAY_MS"),
+            stale_cycle_gap: vars.millis("STALE_CYCLE_GAP_MS"),
+            max_feed_lag: vars.millis("MAX_FEED_LAG_MS"),
+            schedule: vars.optional("MATCH_SCHEDULE"),
+            gas_balance_floor: vars.optional("GAS_BALANCE_FLOOR"),
+            gas_topup_amount: vars.optional("GAS_TOPUP_AMOUNT").unwrap_or(0),
+            standby_failure_threshold: vars.optional("STANDBY_FAILURE_THRESHOLD").unwrap_or(3),
+            primary_cooldown: vars.millis("PRIMARY_COOLDOWN_MS"),
+            clamp_to_onchain: vars.optional("CLAMP_TO_ONCHAIN").unwrap_or(false),
+            min_counterparties: vars.optional("MIN_COUNTERPARTIES"),
+            verify_fills: vars.optional("VERIFY_FILLS").unwrap_or(false),
+            order_whitelist: vars.optional::<String>("MATCH_ORDER_WHITELIST").map(|ids| {
+                ids.split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }),
+            min_order_age: vars.millis("MIN_ORDER_AGE_MS"),
+            volume_anomaly_multiple: vars.optional("VOLUME_ANOMALY_MULTIPLE"),
+            volume_anomaly_window: vars.optional("VOLUME_ANOMALY_WINDOW").unwrap_or(20),
+            price_band: vars.optional("PRICE_BAND"),
+            price_band_window: vars.optional("PRICE_BAND_WINDOW").unwrap_or(50),
+            chunk_retries: vars.optional("CHUNK_RETRIES").unwrap_or(0),
+            retry_backoff: vars
+                .millis("CHUNK_RETRY_BACKOFF_MS")
+                .unwrap_or(Duration::from_millis(200)),
+            retry_jitter: vars.optional("CHUNK_RETRY_JITTER").unwrap_or(0.5),
+            retry_seed: vars.optional("CHUNK_RETRY_SEED"),
+            retry_queue_size: vars.optional("RETRY_QUEUE_SIZE").unwrap_or(256),
+            poison_order_threshold: vars.optional("POISON_ORDER_THRESHOLD"),
+            confirmation: match vars.optional("CONFIRMATION_DEPTH") {
+                Some(depth) => Some(ConfirmationWait {
+                    depth,
+                    timeout: vars
+                        .millis("CONFIRMATION_TIMEOUT_MS")
+                        .unwrap_or(Duration::from_secs(30)),
+                    poll_interval: vars
+                        .positive_millis("CONFIRMATION_POLL_MS")
+                        .unwrap_or(Duration::from_millis(500)),
+                    on_reorg: vars.optional("REORG_POLICY").unwrap_or_default(),
+                }),
+                None => None,
+            },
+            #[cfg(feature = "fault-injection")]
+            fault: match vars.optional("FAULT_FAILURE_RATE") {
+                Some(failure_rate) => Some(FaultConfig {
+                    failure_rate,
+                    delay: vars.millis("FAULT_DELAY_MS"),
+                    seed: vars.optional("FAULT_SEED"),
+                }),
+                None => None,
+            },
+        };
+
+        let owner_rate: Option<f64> = vars.optional("OWNER_RATE_LIMIT");
+        let owner_burst = vars.optional("OWNER_RATE_BURST");
+        let ingest_limits = IngestLimits {
+            max_buy_price: vars.optional("MAX_BUY_PRICE"),
+            min_sell_price: vars.optional("MIN_SELL_PRICE"),
+            min_order_amount: vars.optional("MIN_ORDER_AMOUNT"),
+            tick_size: vars.optional("TICK_SIZE"),
+            strict_ticks: vars.optional("STRICT_TICKS").unwrap_or(false),
+            reingest: vars.optional("REINGEST_POLICY").unwrap_or_default(),
+            max_book_levels: vars.optional("MAX_BOOK_LEVELS"),
+            missing_timestamp: vars
+                .optional("MISSING_TIMESTAMP_POLICY")
+                .unwrap_or_default(),
+            owner_rate_limit: owner_rate.map(|rate| OwnerRateLimit {
+                rate,
+                burst: owner_burst.unwrap_or(rate.max(1.0)),
+            }),
+        };
+
+        let fetch_order_limit = vars.optional("FETCH_ORDER_LIMIT").unwrap_or(100);
+        let provider_url = vars
+            .optional("PROVIDER_URL")
+            .unwrap_or_else(|| "testnet.fuel.network".to_string());
+        let provider_connect_timeout = vars
+            .millis("PROVIDER_CONNECT_TIMEOUT_MS")
+            .unwrap_or(Duration::from_secs(10));
+        let log_sink: LogSinkKind = vars.optional("LOG_SINK").unwrap_or_default();
+        let log_aggregate_window = vars.positive_millis("LOG_AGGREGATE_WINDOW_MS");
+        let log_retention = vars.millis("LOG_RETENTION_MS");
+        let log_retention_interval = vars
+            .positive_millis("LOG_RETENTION_INTERVAL_MS")
+            .unwrap_or(Duration::from_secs(3600));
+        let mutation_log_path = vars.optional("MUTATION_LOG_PATH");
+        let otel_endpoint = vars.optional("OTEL_EXPORTER_OTLP_ENDPOINT");
+        let database_read_url = vars.optional("DATABASE_READ_URL");
+        let standby_mnemonic = vars.optional("STANDBY_MNEMONIC");
+        let book_snapshot_path = vars.optional("BOOK_SNAPSHOT_PATH");
+        let book_snapshot_interval = vars
+            .positive_millis("BOOK_SNAPSHOT_INTERVAL_MS")
+            .unwrap_or(Duration::from_secs(10));
+        let book_snapshot_max_age = vars
+            .millis("BOOK_SNAPSHOT_MAX_AGE_MS")
+            .unwrap_or(Duration::from_secs(60));
+        let restored_cross_policy = vars.optional("RESTORED_CROSS_POLICY").unwrap_or_default();
+        let restored_cross_threshold = vars.optional("RESTORED_CROSS_THRESHOLD").unwrap_or(0);
+        let book_history_interval = vars.positive_millis("BOOK_HISTORY_INTERVAL_MS");
+        let book_history_depth = vars.optional("BOOK_HISTORY_DEPTH").unwrap_or(20);
+        let consistency_check_interval = vars.positive_millis("CONSISTENCY_CHECK_INTERVAL_MS");
+        let database_url = if log_sink == LogSinkKind::Postgres || book_history_interval.is_some() {
+            vars.required::<String>("DATABASE_URL")
+        } else {
+            vars.optional::<String>("DATABASE_URL")
+        };
+
+        if !vars.errors.is_empty() {
+            return Err(Error::ConfigError(vars.errors.join("; ")));
+        }
+
+        // Every required value was recorded as an error above if missing.
+        Ok(Config {
+            websocket_url: websocket_url.unwrap(),
+            fetch_order_limit,
+            database_url,
+            database_read_url,
+            provider_url,
+            provider_connect_timeout,
+            mnemonic: mnemonic.unwrap(),
+            standby_mnemonic,
+            contract_id: contract_id.unwrap(),
+            log_sink,
+            log_aggregate_window,
+            log_retention,
+            log_retention_interval,
+            mutation_log_path,
+            book_snapshot_path,
+            book_snapshot_interval,
+            book_snapshot_max_age,
+            restored_cross_policy,
+            restored_cross_threshold,
+            consistency_check_interval,
+            book_history_interval,
+            book_history_depth,
+            redis,
+            kafka,
+            otel_endpoint,
+            ingest_limits,
+            matcher,
+        })
+    }
+}
+
+/// Reads typed values out of the variable map, collecting every problem
+/// instead of stopping at the first one.
+struct Vars<'a> {
+    vars: &'a HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl<'a> Vars<'a> {
+    fn raw(&self, key: &str) -> Option<&'a str> {
+        self.vars
+            .get(key)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn parse<T: FromStr>(&mut self, key: &str, value: &str) -> Option<T> {
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.errors
+                    .push(format!("{} has invalid value {:?}", key, value));
+                None
+            }
+        }
+    }
+
+    fn required<T: FromStr>(&mut self, key: &str) -> Option<T> {
+        match self.raw(key) {
+            Some(value) => self.parse(key, value),
+            None => {
+                self.errors.push(format!("{} is not set", key));
+                None
+            }
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, key: &str) -> Option<T> {
+        let value = self.raw(key)?;
+        self.parse(key, value)
+    }
+
+    fn millis(&mut self, key: &str) -> Option<Duration> {
+        self.optional(key).map(Duration::from_millis)
+    }
+
+    /// Like `millis`, but records an error for 0, which periodic tasks
+    /// cannot tick at.
+    fn positive_millis(&mut self, key: &str) -> Option<Duration> {
+        match self.millis(key) {
+            Some(duration) if duration.is_zero() => {
+                self.errors.push(format!("{} must be greater than 0", key));
+                None
+            }
+            duration => duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTRACT_ID: &str = "0x0000000000000000000000000000000000000000000000000000000000000001";
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        let mut vars: HashMap<String, String> = [
+            ("WEBSOCKET_URL", "ws://localhost:8080/v1/graphql"),
+            ("MNEMONIC", "test mnemonic"),
+            ("CONTRACT_ID", CONTRACT_ID),
+            ("DATABASE_URL", "postgres://localhost/spark"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        for (key, value) in pairs {
+            vars.insert(key.to_string(), value.to_string());
+        }
+        vars
+    }
+
+    fn error_of(vars: &HashMap<String, String>) -> String {
+        match Config::from_map(vars) {
+            Err(Error::ConfigError(message)) => message,
+            Err(e) => panic!("expected a config error, got {}", e),
+            Ok(_) => panic!("expected a config error"),
+        }
+    }
+
+    #[test]
+    fn fills_in_the_defaults() {
+        let config = Config::from_map(&vars(&[])).unwrap();
+        assert_eq!(config.fetch_order_limit, 100);
+        assert_eq!(config.log_sink, LogSinkKind::Postgres);
+        assert_eq!(config.matcher.match_interval, Duration::from_secs(1));
+        assert_eq!(config.matcher.retry_backoff, Duration::from_millis(200));
+        assert!(config.redis.is_none());
+        assert!(config.matcher.settings.fees.is_none());
+        assert!(config.matcher.confirmation.is_none());
+    }
+
+    #[test]
+    fn blank_values_count_as_unset() {
+        let config = Config::from_map(&vars(&[("FETCH_ORDER_LIMIT", "  ")])).unwrap();
+        assert_eq!(config.fetch_order_limit, 100);
+        assert_eq!(error_of(&vars(&[("MNEMONIC", " ")])), "MNEMONIC is not set");
+    }
+
+    #[test]
+    fn lists_every_missing_required_value() {
+        assert_eq!(
+            error_of(&HashMap::new()),
+            "WEBSOCKET_URL is not set; MNEMONIC is not set; CONTRACT_ID is not set; \
+             DATABASE_URL is not set"
+        );
+    }
+
+    #[test]
+    fn lists_every_invalid_value() {
+        let message = error_of(&vars(&[
+            ("CONTRACT_ID", "0xnot-hex"),
+            ("FETCH_ORDER_LIMIT", "many"),
+            ("SELF_TRADE_PREVENTION", "Sometimes"),
+            ("MATCH_INTERVAL_MS", "0"),
+        ]));
+        assert_eq!(
+            message,
+            "CONTRACT_ID has invalid value \"0xnot-hex\"; \
+             SELF_TRADE_PREVENTION has invalid value \"Sometimes\"; \
+             MATCH_INTERVAL_MS must be greater than 0; \
+             FETCH_ORDER_LIMIT has invalid value \"many\""
+        );
+    }
+
+    #[test]
+    fn redis_ingest_requires_its_url_and_stream() {
+        assert_eq!(
+            error_of(&vars(&[("REDIS_INGEST", "true")])),
+            "REDIS_URL is not set; REDIS_STREAM is not set"
+        );
+
+        let config = Config::from_map(&vars(&[
+            ("REDIS_INGEST", "true"),
+            ("REDIS_URL", "redis://localhost"),
+            ("REDIS_STREAM", "orders"),
+        ]))
+        .unwrap();
+        let redis = config.redis.unwrap();
+        assert_eq!(redis.stream, "orders");
+        assert_eq!(redis.group, "spark-matcher");
+        assert_eq!(redis.consumer, "matcher-1");
+        assert_eq!(redis.dead_letter_stream, None);
+    }
+
+    #[test]
+    fn kafka_export_requires_its_brokers_and_topic() {
+        assert_eq!(
+            error_of(&vars(&[("KAFKA_EXPORT", "true")])),
+            "KAFKA_BROKERS is not set; KAFKA_TOPIC is not set"
+        );
+
+        let config = Config::from_map(&vars(&[
+            ("KAFKA_EXPORT", "true"),
+            ("KAFKA_BROKERS", "localhost:9092"),
+            ("KAFKA_TOPIC", "matches"),
+        ]))
+        .unwrap();
+        let kafka = config.kafka.unwrap();
+        assert_eq!(kafka.topic, "matches");
+        assert_eq!(kafka.buffer, 1024);
+    }
+
+    #[test]
+    fn database_url_is_only_required_when_used() {
+        let mut without = vars(&[("LOG_SINK", "Stdout")]);
+        without.remove("DATABASE_URL");
+        assert!(Config::from_map(&without).unwrap().database_url.is_none());
+
+        without.insert("BOOK_HISTORY_INTERVAL_MS".to_string(), "1000".to_string());
+        assert_eq!(error_of(&without), "DATABASE_URL is not set");
+    }
+
+    #[test]
+    fn either_fee_enables_the_fee_schedule() {
+        let config = Config::from_map(&vars(&[("TAKER_FEE_BPS", "25")])).unwrap();
+        let fees = config.matcher.settings.fees.unwrap();
+        assert_eq!(fees.maker_rebate_bps, 0);
+        assert_eq!(fees.taker_fee_bps, 25);
+    }
+}
@@ -2,23 +2,46 @@ use std::env::VarError;
 
 use thiserror::Error;
 
+use crate::model::MatchRecord;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to connect to WebSocket")]
     WebSocketConnectionError(#[from] tokio_tungstenite::tungstenite::Error),
 
+    #[error("Failed to connect to Fuel node {0}: {1}")]
+    ProviderConnect(String, String),
+
     #[error("Fuel error: {0}")]
     FuelError(#[from] fuels::types::errors::Error),
 
     #[error("Failed to retrieve environment variable {0}")]
     EnvVarError(#[from] VarError),
 
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+
     #[error("Url parse error {0}")]
     UrlParseError(#[from] url::ParseError),
 
+    #[error("Redis error {0}")]
+    RedisError(#[from] redis::RedisError),
+
+    #[error("Match export error {0}")]
+    ExportError(String),
+
+    #[error("Telemetry error {0}")]
+    TelemetryError(String),
+
     #[error("Rocket  error {0}")]
     RocketError(#[from] rocket::Error),
 
+    #[error("Database error {0}")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("IO error {0}")]
+    IoError(#[from] std::io::Error),
+
     #[error("Failed to parse from hex")]
     FromHexParseError(#[from] hex::FromHexError),
 
@@ -28,8 +51,15 @@ pub enum Error {
     #[error("Fuel_crypto private key parsing error")]
     FuelCryptoPrivParseError,
 
-    #[error("Failed to match orders: {0}")]
-    MatchOrdersError(String),
+    /// A chunk submission that failed for good, with the matches it carried
+    /// so they can be looked up on chain.
+    #[error("Failed to match chunk {chunk} of cycle {cycle_id}: {reason} [{}]", describe_matches(.matches))]
+    ChunkFailed {
+        cycle_id: String,
+        chunk: usize,
+        reason: String,
+        matches: Vec<MatchRecord>,
+    },
 
     #[error("Failed to parse order amount: {0}")]
     OrderAmountParseError(String),
@@ -44,8 +74,47 @@ pub enum Error {
     StringParsingError(String),
 }
 
+/// `buy_id/sell_id x amount` per match, comma-separated.
+fn describe_matches(matches: &[MatchRecord]) -> String {
+    matches
+        .iter()
+        .map(|m| format!("{}/{} x {}", m.buy_id, m.sell_id, m.amount))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl From<&str> for Error {
     fn from(s: &str) -> Self {
         Error::StringParsingError(s.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(buy_id: &str, sell_id: &str, amount: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount,
+            price: 10,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    #[test]
+    fn a_failed_chunk_names_its_orders() {
+        let e = Error::ChunkFailed {
+            cycle_id: "c1".to_string(),
+            chunk: 2,
+            reason: "Revert(42)".to_string(),
+            matches: vec![record("0xb1", "0xs1", 5), record("0xb1", "0xs2", 7)],
+        };
+        assert_eq!(
+            e.to_string(),
+            "Failed to match chunk 2 of cycle c1: Revert(42) [0xb1/0xs1 x 5, 0xb1/0xs2 x 7]"
+        );
+    }
+}
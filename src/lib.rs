@@ -0,0 +1,16 @@
+//! The matcher's modules, shared by the binary and the integration tests.
+
+pub mod api;
+pub mod config;
+pub mod error;
+pub mod logger;
+pub mod management;
+pub mod market;
+pub mod match_export;
+pub mod metrics;
+pub mod model;
+pub mod redis_ingest;
+pub mod stats;
+pub mod util;
+pub mod web;
+pub mod websocket;
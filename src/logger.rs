@@ -1,24 +1,76 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{error, info};
 use sqlx::PgPool;
+use std::str::FromStr;
+use std::sync::Arc;
 use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crate::error::Error;
+use crate::management::manager::OrderManager;
+use crate::market::crossing::SkipCounts;
 
 #[derive(Debug)]
 pub struct TransactionLog {
+    pub cycle_id: String,
     pub total_amount: u128,
     pub matches_len: usize,
     pub tx_id: String,
     pub gas_used: u64,
+    /// From the start of the cycle to the end of crossing, which includes
+    /// the snapshot and heap build below.
     pub match_time_ms: i64,
+    /// Copying the book under its read locks.
+    pub snapshot_time_ms: i64,
+    pub heap_build_time_ms: i64,
+    /// From the end of crossing to the first submission: on-chain clamping,
+    /// gas check and chunking.
+    pub prep_time_ms: i64,
     pub buy_orders: usize,
     pub sell_orders: usize,
+    /// Amount left on the cycle's uncrossed orders after crossing.
+    pub unmatched_buy_volume: u128,
+    pub unmatched_sell_volume: u128,
+    pub skips: SkipCounts,
     pub receive_time_ms: i64,
     pub post_time_ms: i64,
 }
 
-pub async fn log_transactions(
-    mut receiver: mpsc::UnboundedReceiver<TransactionLog>,
-    db_pool: PgPool,
-) {
-    while let Some(log) = receiver.recv().await {
+/// Where per-transaction stats end up.
+#[async_trait]
+pub trait LogSink: Send + Sync {
+    async fn write(&self, log: TransactionLog);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogSinkKind {
+    #[default]
+    Postgres,
+    Stdout,
+    Noop,
+}
+
+impl FromStr for LogSinkKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Postgres" => Ok(LogSinkKind::Postgres),
+            "Stdout" => Ok(LogSinkKind::Stdout),
+            "Noop" => Ok(LogSinkKind::Noop),
+            _ => Err(Error::StringParsingError(format!("unknown log sink {}", s))),
+        }
+    }
+}
+
+pub struct PostgresSink {
+    pub db_pool: PgPool,
+}
+
+#[async_trait]
+impl LogSink for PostgresSink {
+    async fn write(&self, log: TransactionLog) {
         let total_amount = log.total_amount.to_string();
         let match_time_ms = log.match_time_ms;
         let buy_orders = log.buy_orders as i32;
@@ -27,11 +79,13 @@ pub async fn log_transactions(
         let total_gas_used = log.gas_used as i32;
         let receive_time_ms = log.receive_time_ms;
         let post_time_ms = log.post_time_ms;
+        let unmatched_buy_volume = log.unmatched_buy_volume.to_string();
+        let unmatched_sell_volume = log.unmatched_sell_volume.to_string();
 
         sqlx::query!(
             r#"
-            INSERT INTO transaction_stats (total_transactions, total_amount, avg_gas_used, total_gas_used, match_time_ms, buy_orders, sell_orders, receive_time_ms, post_time_ms)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            INSERT INTO transaction_stats (total_transactions, total_amount, avg_gas_used, total_gas_used, match_time_ms, buy_orders, sell_orders, receive_time_ms, post_time_ms, cycle_id, snapshot_time_ms, heap_build_time_ms, prep_time_ms, unmatched_buy_volume, unmatched_sell_volume, skipped_zero_amount, skipped_self_trade, skipped_below_notional, skipped_owner_capped)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)
             "#,
             log.matches_len as i32,
             total_amount,
@@ -41,10 +95,216 @@ pub async fn log_transactions(
             buy_orders,
             sell_orders,
             receive_time_ms,
-            post_time_ms
+            post_time_ms,
+            log.cycle_id,
+            log.snapshot_time_ms,
+            log.heap_build_time_ms,
+            log.prep_time_ms,
+            unmatched_buy_volume,
+            unmatched_sell_volume,
+            log.skips.zero_amount as i32,
+            log.skips.self_trade as i32,
+            log.skips.below_notional_floor as i32,
+            log.skips.owner_capped as i32
         )
-        .execute(&db_pool)
+        .execute(&self.db_pool)
         .await
         .expect("Failed to log transaction");
     }
 }
+
+pub struct StdoutSink;
+
+#[async_trait]
+impl LogSink for StdoutSink {
+    async fn write(&self, log: TransactionLog) {
+        info!("Transaction stats: {:?}", log);
+    }
+}
+
+pub struct NoopSink;
+
+#[async_trait]
+impl LogSink for NoopSink {
+    async fn write(&self, _log: TransactionLog) {}
+}
+
+/// Deletes `transaction_stats` rows older than `max_age` every `interval`.
+pub async fn prune_transactions(db_pool: PgPool, max_age: Duration, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let cutoff = Utc::now().timestamp() as f64 - max_age.as_secs_f64();
+        match sqlx::query!(
+            "DELETE FROM transaction_stats WHERE created_at < to_timestamp($1)",
+            cutoff,
+        )
+        .execute(&db_pool)
+        .await
+        {
+            Ok(result) => info!(
+                "Pruned {} transaction stats rows older than {:?}",
+                result.rows_affected(),
+                max_age
+            ),
+            Err(e) => error!("Failed to prune transaction stats: {:?}", e),
+        }
+    }
+}
+
+/// Writes the top `depth` levels of each side to `book_history` every
+/// `interval`, so past states of the book can be looked up later.
+pub async fn record_book_history(
+    db_pool: PgPool,
+    order_manager: Arc<OrderManager>,
+    depth: usize,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let snapshot = order_manager.l2_snapshot(depth).await;
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO book_history (sequence, bids, asks) VALUES ($1, $2, $3)",
+            snapshot.sequence as i64,
+            serde_json::json!(snapshot.bids),
+            serde_json::json!(snapshot.asks),
+        )
+        .execute(&db_pool)
+        .await
+        {
+            error!("Failed to record book history: {:?}", e);
+        }
+    }
+}
+
+/// Drains the log channel into `sink`. With a `window`, the logs received
+/// in each window are collapsed into one summary row (summed volume,
+/// matches and gas, worst-case latencies, latest book sizes and skips)
+/// before being written.
+pub async fn log_transactions(
+    mut receiver: mpsc::UnboundedReceiver<TransactionLog>,
+    sink: Box<dyn LogSink>,
+    window: Option<Duration>,
+) {
+    let Some(window) = window else {
+        while let Some(log) = receiver.recv().await {
+            sink.write(log).await;
+        }
+        return;
+    };
+
+    let mut pending: Vec<TransactionLog> = Vec::new();
+    let mut ticker = tokio::time::interval(window);
+    loop {
+        tokio::select! {
+            log = receiver.recv() => match log {
+                Some(log) => pending.push(log),
+                None => break,
+            },
+            _ = ticker.tick() => {
+                if let Some(summary) = aggregate(std::mem::take(&mut pending)) {
+                    sink.write(summary).await;
+                }
+            }
+        }
+    }
+
+    if let Some(summary) = aggregate(pending) {
+        sink.write(summary).await;
+    }
+}
+
+fn aggregate(logs: Vec<TransactionLog>) -> Option<TransactionLog> {
+    let mut logs = logs.into_iter();
+    let mut summary = logs.next()?;
+    for log in logs {
+        summary.total_amount = summary.total_amount.saturating_add(log.total_amount);
+        summary.matches_len += log.matches_len;
+        summary.gas_used = summary.gas_used.saturating_add(log.gas_used);
+        summary.match_time_ms = summary.match_time_ms.max(log.match_time_ms);
+        summary.snapshot_time_ms = summary.snapshot_time_ms.max(log.snapshot_time_ms);
+        summary.heap_build_time_ms = summary.heap_build_time_ms.max(log.heap_build_time_ms);
+        summary.prep_time_ms = summary.prep_time_ms.max(log.prep_time_ms);
+        summary.receive_time_ms = summary.receive_time_ms.max(log.receive_time_ms);
+        summary.post_time_ms = summary.post_time_ms.max(log.post_time_ms);
+        summary.buy_orders = log.buy_orders;
+        summary.sell_orders = log.sell_orders;
+        summary.unmatched_buy_volume = log.unmatched_buy_volume;
+        summary.unmatched_sell_volume = log.unmatched_sell_volume;
+        summary.skips = log.skips;
+        summary.cycle_id = log.cycle_id;
+        summary.tx_id = log.tx_id;
+    }
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(cycle_id: &str, total_amount: u128, gas_used: u64, post_time_ms: i64) -> TransactionLog {
+        TransactionLog {
+            cycle_id: cycle_id.to_string(),
+            total_amount,
+            matches_len: 2,
+            tx_id: format!("tx-{}", cycle_id),
+            gas_used,
+            match_time_ms: 1,
+            snapshot_time_ms: 1,
+            heap_build_time_ms: 1,
+            prep_time_ms: 1,
+            buy_orders: 10,
+            sell_orders: 10,
+            unmatched_buy_volume: 0,
+            unmatched_sell_volume: 0,
+            skips: SkipCounts::default(),
+            receive_time_ms: 1,
+            post_time_ms,
+        }
+    }
+
+    struct MemorySink(Arc<std::sync::Mutex<Vec<TransactionLog>>>);
+
+    #[async_trait]
+    impl LogSink for MemorySink {
+        async fn write(&self, log: TransactionLog) {
+            self.0.lock().unwrap().push(log);
+        }
+    }
+
+    #[test]
+    fn aggregate_sums_volume_and_keeps_the_worst_latency() {
+        assert!(aggregate(Vec::new()).is_none());
+
+        let mut last = log("c3", u128::MAX, 5, 20);
+        last.buy_orders = 4;
+        let summary = aggregate(vec![log("c1", 10, 100, 50), log("c2", 5, 7, 30), last]).unwrap();
+        assert_eq!(summary.total_amount, u128::MAX);
+        assert_eq!(summary.matches_len, 6);
+        assert_eq!(summary.gas_used, 112);
+        assert_eq!(summary.post_time_ms, 50);
+        assert_eq!((summary.cycle_id.as_str(), summary.buy_orders), ("c3", 4));
+    }
+
+    #[tokio::test]
+    async fn cycles_within_a_window_collapse_into_one_row() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        for n in 1..=3 {
+            sender.send(log(&format!("c{}", n), 10, 1, 1)).unwrap();
+        }
+        drop(sender);
+
+        let rows = Arc::new(std::sync::Mutex::new(Vec::new()));
+        log_transactions(
+            receiver,
+            Box::new(MemorySink(rows.clone())),
+            Some(Duration::from_secs(3600)),
+        )
+        .await;
+
+        let rows = rows.lock().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].total_amount, rows[0].matches_len), (30, 6));
+    }
+}
@@ -1,41 +1,123 @@
-use market::SparkMatcher;
 use sqlx::PgPool;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::signal;
 use tokio::sync::mpsc;
 
-mod api;
-mod config;
-mod error;
-mod logger;
-mod management;
-mod market;
-mod model;
-mod util;
-mod web;
-mod websocket;
-
-use crate::error::Error;
-use management::manager::OrderManager;
-use url::Url;
-use websocket::client::WebSocketClient;
+use spark_matcher::config::Config;
+use spark_matcher::error::Error;
+use spark_matcher::management::consistency;
+use spark_matcher::management::manager::OrderManager;
+use spark_matcher::management::mutation_log::MutationLog;
+use spark_matcher::management::snapshot::{self, CrossedBookPolicy};
+use spark_matcher::market::SparkMatcher;
+use spark_matcher::match_export::{KafkaPublisher, MatchExporter};
+use spark_matcher::metrics::Metrics;
+use spark_matcher::websocket::client::WebSocketClient;
+use spark_matcher::{logger, util, web};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     dotenv::dotenv().ok();
 
-    let ws_url = Url::parse(&config::ev("WEBSOCKET_URL")?)?;
+    let config = Config::from_env()?;
+    if let Some(endpoint) = &config.otel_endpoint {
+        util::telemetry::init(endpoint)?;
+    }
 
-    let websocket_client = WebSocketClient::new(ws_url);
-    let order_manager = OrderManager::new();
+    let mutation_log = match &config.mutation_log_path {
+        Some(path) => Some(MutationLog::open(path)?),
+        None => None,
+    };
+    let order_manager = OrderManager::with_config(config.ingest_limits.clone(), mutation_log);
+    let websocket_client = WebSocketClient::new(
+        config.websocket_url.clone(),
+        config.fetch_order_limit,
+        order_manager.resync_requested.clone(),
+    );
     let arc_order_manager = order_manager.clone();
 
-    let database_url = config::ev("DATABASE_URL")?;
-    let db_pool = PgPool::connect(&database_url).await.unwrap();
+    let mut restored = 0;
+    if let Some(path) = &config.book_snapshot_path {
+        let path = PathBuf::from(path);
+        restored =
+            snapshot::restore_book(&order_manager, &path, config.book_snapshot_max_age).await?;
+        tokio::spawn(snapshot::persist_book(
+            order_manager.clone(),
+            path,
+            config.book_snapshot_interval,
+        ));
+    }
+
+    // The web server only reads, so it can use a replica; stats are written
+    // through the matcher's own pool on the primary.
+    let db_pool = match config
+        .database_read_url
+        .as_ref()
+        .or(config.database_url.as_ref())
+    {
+        Some(read_url) => Some(PgPool::connect(read_url).await.unwrap()),
+        None => None,
+    };
+
+    let metrics = Metrics::new();
+    let mut spark_matcher =
+        SparkMatcher::new(arc_order_manager.clone(), metrics.clone(), &config).await?;
+    if let Some(kafka) = &config.kafka {
+        let publisher = KafkaPublisher::new(&kafka.brokers, kafka.topic.clone())?;
+        let exporter = MatchExporter::spawn(
+            config.contract_id.to_string(),
+            Arc::new(publisher),
+            kafka.buffer,
+            metrics.clone(),
+        );
+        spark_matcher.set_on_cycle(move |report| {
+            exporter.export(&report);
+            std::future::ready(())
+        });
+    }
+    let paused = spark_matcher.paused.clone();
+    if restored > 0
+        && config.restored_cross_policy == CrossedBookPolicy::Pause
+        && snapshot::is_crossed_beyond(&order_manager, config.restored_cross_threshold).await
+    {
+        log::warn!(
+            "Pausing matching until the restored book is reviewed and POST /resume is called"
+        );
+        paused.pause();
+    }
+
+    if let Some(interval) = config.consistency_check_interval {
+        tokio::spawn(consistency::check_book_periodically(
+            arc_order_manager.clone(),
+            metrics.clone(),
+            interval,
+        ));
+    }
 
-    let spark_matcher = SparkMatcher::new(arc_order_manager.clone()).await?;
+    if let Some(interval) = config.book_history_interval {
+        // Config requires DATABASE_URL whenever the history is enabled.
+        let database_url = config.database_url.as_ref().unwrap();
+        let history_pool = PgPool::connect(database_url).await.unwrap();
+        tokio::spawn(logger::record_book_history(
+            history_pool,
+            arc_order_manager.clone(),
+            config.book_history_depth,
+            interval,
+        ));
+    }
 
     let (tx, mut rx) = mpsc::channel(100);
 
+    if let Some(redis_ingest) = config.redis.clone() {
+        let redis_tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = redis_ingest.run(redis_tx).await {
+                eprintln!("Redis ingest error: {}", e);
+            }
+        });
+    }
+
     let ws_task = tokio::spawn(async move {
         if let Err(e) = websocket_client.connect(tx).await {
             eprintln!("WebSocket error: {}", e);
@@ -43,8 +125,8 @@ async fn main() -> Result<(), Error> {
     });
 
     let manager_task = tokio::spawn(async move {
-        while let Some(order) = rx.recv().await {
-            order_manager.add_order(order).await;
+        while let Some(event) = rx.recv().await {
+            order_manager.apply_event(event).await;
         }
     });
 
@@ -54,8 +136,10 @@ async fn main() -> Result<(), Error> {
         }
     });
 
+    let match_settings = config.matcher.settings.clone();
     let rocket_task = tokio::spawn(async {
-        let rocket = web::server::rocket(db_pool, arc_order_manager);
+        let rocket =
+            web::server::rocket(db_pool, arc_order_manager, metrics, match_settings, paused);
         let _ = rocket.launch().await;
     });
 
@@ -73,5 +157,6 @@ async fn main() -> Result<(), Error> {
     };
 
     println!("Application is shutting down.");
+    util::telemetry::shutdown();
     Ok(())
 }
@@ -0,0 +1,132 @@
+use log::error;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::manager::OrderManager;
+use crate::metrics::Metrics;
+use crate::model::SpotOrder;
+
+/// Checks the book's invariants under both read locks and describes every
+/// violation found: an id on both sides, an order keyed under a price other
+/// than its own, a zero-amount order, or an empty level.
+pub async fn check_book(order_manager: &OrderManager) -> Vec<String> {
+    let buy_orders = order_manager.buy_orders.read().await;
+    let sell_orders = order_manager.sell_orders.read().await;
+
+    let mut violations = Vec::new();
+    check_side("buy", &buy_orders, &mut violations);
+    check_side("sell", &sell_orders, &mut violations);
+
+    let buy_ids: HashSet<&str> = buy_orders
+        .values()
+        .flatten()
+        .map(|order| order.id.as_str())
+        .collect();
+    for order in sell_orders.values().flatten() {
+        if buy_ids.contains(order.id.as_str()) {
+            violations.push(format!("order {} is on both sides", order.id));
+        }
+    }
+    violations
+}
+
+fn check_side(side: &str, orders: &BTreeMap<u128, Vec<SpotOrder>>, violations: &mut Vec<String>) {
+    for (price, level) in orders {
+        if level.is_empty() {
+            violations.push(format!("empty {} level at {}", side, price));
+        }
+        for order in level {
+            if order.price != *price {
+                violations.push(format!(
+                    "{} order {} with price {} keyed under {}",
+                    side, order.id, order.price, price
+                ));
+            }
+            if order.amount == 0 {
+                violations.push(format!("{} order {} has zero amount", side, order.id));
+            }
+        }
+    }
+}
+
+/// Runs `check_book` every `interval`, logging each violation and adding
+/// their count to `book_invariant_violations`.
+pub async fn check_book_periodically(
+    order_manager: Arc<OrderManager>,
+    metrics: Arc<Metrics>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let violations = check_book(&order_manager).await;
+        if violations.is_empty() {
+            continue;
+        }
+        for violation in &violations {
+            error!("Book invariant violated: {}", violation);
+        }
+        metrics
+            .book_invariant_violations
+            .fetch_add(violations.len() as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::OrderType;
+
+    fn order(id: &str, order_type: OrderType, amount: u128, price: u128) -> SpotOrder {
+        SpotOrder {
+            id: id.to_string(),
+            user: "alice".to_string(),
+            asset: String::new(),
+            amount,
+            price,
+            timestamp: 1,
+            order_type,
+            tag: None,
+            received_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_booked_book_is_consistent() {
+        let manager = OrderManager::new();
+        manager
+            .add_orders(vec![
+                order("b1", OrderType::Buy, 5, 9),
+                order("b2", OrderType::Buy, 5, 9),
+                order("s1", OrderType::Sell, 5, 11),
+            ])
+            .await;
+        manager.remove_order("b1", 9, OrderType::Buy).await;
+        assert!(check_book(&manager).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_every_corruption() {
+        let manager = OrderManager::new();
+        {
+            let mut buy_orders = manager.buy_orders.write().await;
+            buy_orders.insert(9, vec![order("b1", OrderType::Buy, 5, 8)]);
+            buy_orders.insert(10, vec![order("b2", OrderType::Buy, 0, 10)]);
+            buy_orders.insert(11, Vec::new());
+            let mut sell_orders = manager.sell_orders.write().await;
+            sell_orders.insert(12, vec![order("b2", OrderType::Sell, 5, 12)]);
+        }
+
+        assert_eq!(
+            check_book(&manager).await,
+            [
+                "buy order b1 with price 8 keyed under 9",
+                "buy order b2 has zero amount",
+                "empty buy level at 11",
+                "order b2 is on both sides",
+            ]
+        );
+    }
+}
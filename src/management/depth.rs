@@ -0,0 +1,72 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::model::SpotOrder;
+
+/// Aggregated L2 view of the book. Each level is `[price, quantity]` as
+/// decimal strings of raw contract units, best level first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct L2Snapshot {
+    /// Number of book mutations applied so far; a client that sees the same
+    /// value twice can skip re-rendering.
+    pub sequence: u64,
+    pub bids: Vec<[String; 2]>,
+    pub asks: Vec<[String; 2]>,
+}
+
+/// Sums the `(price, amounts)` levels into `[price, quantity]` pairs, keeping
+/// at most `depth` non-empty ones in iteration order.
+pub fn aggregate_levels<'a, I>(levels: I, depth: usize) -> Vec<[String; 2]>
+where
+    I: Iterator<Item = (&'a u128, &'a Vec<SpotOrder>)>,
+{
+    levels
+        .map(|(price, orders)| {
+            let quantity = orders
+                .iter()
+                .fold(0u128, |sum, order| sum.saturating_add(order.amount));
+            (*price, quantity)
+        })
+        .filter(|(_, quantity)| *quantity > 0)
+        .take(depth)
+        .map(|(price, quantity)| [price.to_string(), quantity.to_string()])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::OrderType;
+    use std::collections::BTreeMap;
+
+    fn order(amount: u128, price: u128) -> SpotOrder {
+        SpotOrder {
+            id: format!("o{}-{}", price, amount),
+            user: "alice".to_string(),
+            asset: String::new(),
+            amount,
+            price,
+            timestamp: 1,
+            order_type: OrderType::Sell,
+            tag: None,
+            received_at: 0,
+        }
+    }
+
+    #[test]
+    fn sums_levels_and_skips_empty_ones() {
+        let mut levels: BTreeMap<u128, Vec<SpotOrder>> = BTreeMap::new();
+        levels.insert(10, vec![order(3, 10), order(4, 10)]);
+        levels.insert(11, vec![order(0, 11)]);
+        levels.insert(12, vec![order(u128::MAX, 12), order(1, 12)]);
+        levels.insert(13, vec![order(5, 13)]);
+
+        let level = |price: &str, quantity: &str| [price.to_string(), quantity.to_string()];
+        assert_eq!(
+            aggregate_levels(levels.iter(), 2),
+            [level("10", "7"), level("12", &u128::MAX.to_string())]
+        );
+        assert_eq!(aggregate_levels(levels.iter().rev(), 1), [level("13", "5")]);
+        assert!(aggregate_levels(levels.iter(), 0).is_empty());
+    }
+}
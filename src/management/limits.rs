@@ -0,0 +1,270 @@
+use std::str::FromStr;
+
+use super::rate_limit::OwnerRateLimit;
+use crate::error::Error;
+use crate::model::{OrderType, SpotOrder};
+
+/// What to do with an order whose id is already in the book, e.g. when the
+/// feed re-sends its snapshot after a reconnect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReingestPolicy {
+    /// Replace the booked order, moving it to its new price level.
+    #[default]
+    Update,
+    /// Keep the booked order and drop the new one.
+    Reject,
+}
+
+impl FromStr for ReingestPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Update" => Ok(ReingestPolicy::Update),
+            "Reject" => Ok(ReingestPolicy::Reject),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown reingest policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// What to do with an order whose feed sent no timestamp (0), which time
+/// priority would otherwise rank ahead of every other order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingTimestampPolicy {
+    /// Use the time it was first booked, kept when the feed re-sends it. It
+    /// then queues behind orders booked earlier at its price, even ones
+    /// created after it.
+    #[default]
+    AssignIngestTime,
+    Reject,
+}
+
+impl FromStr for MissingTimestampPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "AssignIngestTime" => Ok(MissingTimestampPolicy::AssignIngestTime),
+            "Reject" => Ok(MissingTimestampPolicy::Reject),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown missing timestamp policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Sanity checks applied to every order before it enters the book.
+#[derive(Debug, Clone, Default)]
+pub struct IngestLimits {
+    /// A buy above this would sit at the top of the bid and cross everything.
+    pub max_buy_price: Option<u128>,
+    /// A sell below this would sit at the top of the ask and cross everything.
+    pub min_sell_price: Option<u128>,
+    /// The market's minimum order size in raw base-asset units; smaller
+    /// orders could never be filled and only add dust to the book.
+    pub min_order_amount: Option<u128>,
+    /// Price grid the book is keyed on. Off-grid buys are rounded down and
+    /// sells up to the next tick, or both rejected with `strict_ticks`.
+    pub tick_size: Option<u128>,
+    pub strict_ticks: bool,
+    pub reingest: ReingestPolicy,
+    /// Price levels kept per side. Past it the level farthest from the
+    /// touch is evicted with all its orders.
+    pub max_book_levels: Option<usize>,
+    pub missing_timestamp: MissingTimestampPolicy,
+    /// Per-user cap on orders arriving through `add_order`. Snapshots
+    /// booked through `add_orders` are not counted.
+    pub owner_rate_limit: Option<OwnerRateLimit>,
+}
+
+impl IngestLimits {
+    /// Moves the price onto the tick grid, away from the other side: buys
+    /// round down and sells up, so rounding never makes two orders cross
+    /// locally that do not cross at their real prices on chain. Returns why
+    /// the order must be rejected if it is off-grid in strict mode or would
+    /// round to zero.
+    pub fn align_to_tick(&self, order: &mut SpotOrder) -> Option<String> {
+        let tick = match self.tick_size {
+            Some(tick) if tick > 1 => tick,
+            _ => return None,
+        };
+        let offset = order.price % tick;
+        if offset == 0 {
+            return None;
+        }
+        if self.strict_ticks {
+            return Some(format!(
+                "price {} not a multiple of tick {}",
+                order.price, tick
+            ));
+        }
+
+        let rounded = match order.order_type {
+            OrderType::Buy => order.price - offset,
+            OrderType::Sell => order.price.saturating_add(tick - offset),
+        };
+        if rounded == 0 {
+            return Some(format!(
+                "price {} rounds to zero at tick {}",
+                order.price, tick
+            ));
+        }
+        order.price = rounded;
+        None
+    }
+
+    /// Returns why a timestamp-less order must be rejected. Under
+    /// `AssignIngestTime` the order manager fills the timestamp in when
+    /// booking, since only it knows whether the id was booked before.
+    pub fn check_timestamp(&self, order: &SpotOrder) -> Option<String> {
+        if order.timestamp == 0 && self.missing_timestamp == MissingTimestampPolicy::Reject {
+            return Some("no timestamp".to_string());
+        }
+        None
+    }
+
+    /// Returns why the order must be rejected, or `None` if it may be booked.
+    pub fn check(&self, order: &SpotOrder) -> Option<String> {
+        if order.amount == 0 {
+            return Some("zero amount".to_string());
+        }
+
+        if let Some(min) = self.min_order_amount {
+            if order.amount < min {
+                return Some(format!("amount {} below minimum {}", order.amount, min));
+            }
+        }
+
+        match order.order_type {
+            OrderType::Buy => match self.max_buy_price {
+                Some(max) if order.price > max => {
+                    Some(format!("buy price {} above limit {}", order.price, max))
+                }
+                _ => None,
+            },
+            OrderType::Sell => match self.min_sell_price {
+                Some(min) if order.price < min => {
+                    Some(format!("sell price {} below limit {}", order.price, min))
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(order_type: OrderType, amount: u128, price: u128) -> SpotOrder {
+        SpotOrder {
+            id: "o1".to_string(),
+            user: "alice".to_string(),
+            asset: String::new(),
+            amount,
+            price,
+            timestamp: 1,
+            order_type,
+            tag: None,
+            received_at: 0,
+        }
+    }
+
+    fn ticks(tick_size: u128, strict_ticks: bool) -> IngestLimits {
+        IngestLimits {
+            tick_size: Some(tick_size),
+            strict_ticks,
+            ..Default::default()
+        }
+    }
+
+    /// The aligned price, or why the order was rejected.
+    fn aligned(limits: &IngestLimits, order_type: OrderType, price: u128) -> Result<u128, String> {
+        let mut order = order(order_type, 1, price);
+        match limits.align_to_tick(&mut order) {
+            Some(reason) => Err(reason),
+            None => Ok(order.price),
+        }
+    }
+
+    #[test]
+    fn rejects_buys_above_and_sells_below_the_price_bounds() {
+        let limits = IngestLimits {
+            max_buy_price: Some(100),
+            min_sell_price: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(limits.check(&order(OrderType::Buy, 1, 100)), None);
+        assert_eq!(
+            limits.check(&order(OrderType::Buy, 1, u128::MAX)),
+            Some(format!("buy price {} above limit 100", u128::MAX))
+        );
+        assert_eq!(limits.check(&order(OrderType::Sell, 1, 10)), None);
+        assert_eq!(
+            limits.check(&order(OrderType::Sell, 1, 9)),
+            Some("sell price 9 below limit 10".to_string())
+        );
+        // Each bound only applies to its own side.
+        assert_eq!(limits.check(&order(OrderType::Sell, 1, u128::MAX)), None);
+        assert_eq!(limits.check(&order(OrderType::Buy, 1, 1)), None);
+    }
+
+    #[test]
+    fn rejects_zero_amounts_on_either_side() {
+        let limits = IngestLimits::default();
+        for order_type in [OrderType::Buy, OrderType::Sell] {
+            assert_eq!(
+                limits.check(&order(order_type, 0, 10)),
+                Some("zero amount".to_string())
+            );
+            assert_eq!(limits.check(&order(order_type, 1, 10)), None);
+        }
+    }
+
+    #[test]
+    fn rejects_orders_below_the_minimum_amount() {
+        let limits = IngestLimits {
+            min_order_amount: Some(1_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.check(&order(OrderType::Buy, 999, 10)),
+            Some("amount 999 below minimum 1000".to_string())
+        );
+        assert_eq!(limits.check(&order(OrderType::Sell, 1_000, 10)), None);
+        assert_eq!(
+            IngestLimits::default().check(&order(OrderType::Buy, 1, 10)),
+            None
+        );
+    }
+
+    #[test]
+    fn rounds_buys_down_and_sells_up() {
+        let limits = ticks(10, false);
+        assert_eq!(aligned(&limits, OrderType::Buy, 127), Ok(120));
+        assert_eq!(aligned(&limits, OrderType::Sell, 121), Ok(130));
+        assert_eq!(aligned(&limits, OrderType::Buy, 120), Ok(120));
+        assert_eq!(aligned(&limits, OrderType::Sell, 120), Ok(120));
+        assert_eq!(
+            aligned(&limits, OrderType::Buy, 7),
+            Err("price 7 rounds to zero at tick 10".to_string())
+        );
+        assert_eq!(aligned(&limits, OrderType::Sell, 7), Ok(10));
+    }
+
+    #[test]
+    fn strict_ticks_reject_off_grid_prices_on_both_sides() {
+        let limits = ticks(10, true);
+        for order_type in [OrderType::Buy, OrderType::Sell] {
+            assert_eq!(aligned(&limits, order_type, 120), Ok(120));
+            assert_eq!(
+                aligned(&limits, order_type, 125),
+                Err("price 125 not a multiple of tick 10".to_string())
+            );
+        }
+    }
+}
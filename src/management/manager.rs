@@ -1,35 +1,272 @@
-use crate::model::{OrderType, SpotOrder};
-use log::info;
-use std::collections::BTreeMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use super::depth::{aggregate_levels, L2Snapshot};
+use super::limits::{IngestLimits, ReingestPolicy};
+use super::mutation_log::{Mutation, MutationLog};
+use super::rate_limit::OwnerRateLimiter;
+use super::snapshot::BookSnapshot;
+use crate::error::Error;
+use crate::model::{MatchRecord, OrderEvent, OrderType, SpotOrder};
+use chrono::Utc;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::{Notify, RwLock};
 
 pub struct OrderManager {
     pub buy_orders: RwLock<BTreeMap<u128, Vec<SpotOrder>>>,
     pub sell_orders: RwLock<BTreeMap<u128, Vec<SpotOrder>>>,
+    pub mutation_log: Option<MutationLog>,
+    pub limits: IngestLimits,
+    /// Bumped on every mutation, under the write lock that applies it.
+    pub sequence: AtomicU64,
+    /// Unix millis of the last order streamed in through `add_order`, booked
+    /// or not; 0 until the first one. A restored snapshot does not count.
+    pub last_ingest_ms: AtomicI64,
+    /// Woken by `resync`; the websocket feed resubscribes when it fires.
+    pub resync_requested: Arc<Notify>,
+    rate_limiter: Option<OwnerRateLimiter>,
+    /// Price level of every booked id per side, so inserts need not scan the
+    /// book. Only changed while holding that side's write lock.
+    buy_index: Mutex<HashMap<String, u128>>,
+    sell_index: Mutex<HashMap<String, u128>>,
 }
 
 impl OrderManager {
     pub fn new() -> Arc<Self> {
-        Arc::new(Self {
+        Arc::new(Self::empty(IngestLimits::default(), None))
+    }
+
+    pub fn with_config(limits: IngestLimits, mutation_log: Option<MutationLog>) -> Arc<Self> {
+        Arc::new(Self::empty(limits, mutation_log))
+    }
+
+    fn empty(limits: IngestLimits, mutation_log: Option<MutationLog>) -> Self {
+        Self {
             buy_orders: RwLock::new(BTreeMap::new()),
             sell_orders: RwLock::new(BTreeMap::new()),
-        })
+            mutation_log,
+            rate_limiter: limits.owner_rate_limit.map(OwnerRateLimiter::new),
+            limits,
+            sequence: AtomicU64::new(0),
+            last_ingest_ms: AtomicI64::new(0),
+            resync_requested: Arc::new(Notify::new()),
+            buy_index: Mutex::new(HashMap::new()),
+            sell_index: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn index(&self, order_type: OrderType) -> MutexGuard<'_, HashMap<String, u128>> {
+        match order_type {
+            OrderType::Buy => self.buy_index.lock().unwrap(),
+            OrderType::Sell => self.sell_index.lock().unwrap(),
+        }
+    }
+
+    /// Rebuilds a book by applying a recorded mutation log from the start.
+    /// The replayed manager does not record further mutations.
+    pub async fn replay<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let manager = Self::empty(IngestLimits::default(), None);
+        for mutation in MutationLog::read(path)? {
+            match mutation {
+                Mutation::Add(order) => {
+                    manager.add_order(order).await;
+                }
+                Mutation::Remove {
+                    order_id,
+                    price,
+                    order_type,
+                } => manager.remove_order(&order_id, price, order_type).await,
+                Mutation::Clear => manager.clear_orders().await,
+            }
+        }
+        Ok(manager)
+    }
+
+    fn touch_ingest(&self) {
+        self.last_ingest_ms
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// Time since the feed last delivered an order, `None` if it never has.
+    pub fn feed_lag(&self) -> Option<Duration> {
+        match self.last_ingest_ms.load(Ordering::Relaxed) {
+            0 => None,
+            last => Some(Duration::from_millis(
+                (Utc::now().timestamp_millis() - last).max(0) as u64,
+            )),
+        }
     }
 
-    pub async fn add_order(&self, order: SpotOrder) {
+    fn record(&self, mutation: Mutation) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        if let Some(mutation_log) = &self.mutation_log {
+            mutation_log.record(&mutation);
+        }
+    }
+
+    /// Books the order; an order already booked under the same id is handled
+    /// per the reingest policy. Returns `false` if the order was rejected.
+    pub async fn add_order(&self, mut order: SpotOrder) -> bool {
+        self.touch_ingest();
+        if let Some(reason) = self
+            .limits
+            .align_to_tick(&mut order)
+            .or_else(|| self.limits.check_timestamp(&order))
+            .or_else(|| self.limits.check(&order))
+        {
+            warn!("Rejected order {}: {}", order.id, reason);
+            return false;
+        }
+
+        if let Some(limiter) = &self.rate_limiter {
+            if !limiter.allow(&order.user) {
+                warn!(
+                    "Rejected order {}: user {} over {} orders/s",
+                    order.id, order.user, limiter.limit.rate
+                );
+                return false;
+            }
+        }
+
         let mut order_map = match order.order_type {
             OrderType::Buy => self.buy_orders.write().await,
             OrderType::Sell => self.sell_orders.write().await,
         };
+        self.insert(&mut order_map, order)
+    }
+
+    /// Books a snapshot of orders under a single acquisition of both write
+    /// locks. Returns `(accepted, rejected)` counts.
+    pub async fn add_orders(&self, orders: Vec<SpotOrder>) -> (usize, usize) {
+        let mut buy_orders = self.buy_orders.write().await;
+        let mut sell_orders = self.sell_orders.write().await;
+
+        let mut accepted = 0;
+        let mut rejected = 0;
+        for mut order in orders {
+            if let Some(reason) = self
+                .limits
+                .align_to_tick(&mut order)
+                .or_else(|| self.limits.check_timestamp(&order))
+                .or_else(|| self.limits.check(&order))
+            {
+                warn!("Rejected order {}: {}", order.id, reason);
+                rejected += 1;
+                continue;
+            }
+            let booked = match order.order_type {
+                OrderType::Buy => self.insert(&mut buy_orders, order),
+                OrderType::Sell => self.insert(&mut sell_orders, order),
+            };
+            if booked {
+                accepted += 1;
+            } else {
+                rejected += 1;
+            }
+        }
+        (accepted, rejected)
+    }
+
+    /// Returns `false` if the id is already booked and the reingest policy
+    /// rejects it, or if its level is evicted right away by the level cap.
+    fn insert(&self, order_map: &mut BTreeMap<u128, Vec<SpotOrder>>, mut order: SpotOrder) -> bool {
+        let booked_price = self.index(order.order_type).get(&order.id).copied();
+        let booked = booked_price.and_then(|price| {
+            order_map
+                .get(&price)?
+                .iter()
+                .find(|o| o.id == order.id)
+                .map(|o| (price, o.received_at, o.timestamp))
+        });
+
+        if order.received_at == 0 {
+            order.received_at = match booked {
+                Some((_, received_at, _)) => received_at,
+                None => Utc::now().timestamp_millis(),
+            };
+        }
+        // Only reached when the missing timestamp policy assigns one; a
+        // re-sent order keeps the one it was first booked with.
+        if order.timestamp == 0 {
+            order.timestamp = match booked {
+                Some((_, _, timestamp)) => timestamp,
+                None => Utc::now().timestamp() as u64,
+            };
+        }
+
+        if let Some((price, _, _)) = booked {
+            if self.limits.reingest == ReingestPolicy::Reject {
+                warn!("Rejected order {}: id already in the book", order.id);
+                return false;
+            }
+            if price != order.price {
+                self.record(Mutation::Remove {
+                    order_id: order.id.clone(),
+                    price,
+                    order_type: order.order_type,
+                });
+                if let Some(orders) = order_map.get_mut(&price) {
+                    orders.retain(|o| o.id != order.id);
+                    if orders.is_empty() {
+                        order_map.remove(&price);
+                    }
+                }
+            }
+        }
+
+        self.record(Mutation::Add(order.clone()));
 
-        let orders = order_map.entry(order.price).or_default();
+        let (price, order_type) = (order.price, order.order_type);
+        let orders = order_map.entry(price).or_default();
 
+        self.index(order_type).insert(order.id.clone(), price);
         if let Some(existing_order) = orders.iter_mut().find(|o| o.id == order.id) {
             *existing_order = order;
         } else {
             orders.push(order);
         }
+
+        self.evict_far_levels(order_map, order_type);
+        order_map.contains_key(&price)
+    }
+
+    /// Drops whole levels farthest from the touch (lowest bids, highest
+    /// asks) until the side is within `max_book_levels`.
+    fn evict_far_levels(
+        &self,
+        order_map: &mut BTreeMap<u128, Vec<SpotOrder>>,
+        order_type: OrderType,
+    ) {
+        let Some(max_levels) = self.limits.max_book_levels else {
+            return;
+        };
+        while order_map.len() > max_levels {
+            let evicted = match order_type {
+                OrderType::Buy => order_map.pop_first(),
+                OrderType::Sell => order_map.pop_last(),
+            };
+            let Some((price, orders)) = evicted else {
+                break;
+            };
+            warn!(
+                "{:?} side over {} price levels, evicted level {} with {} order(s)",
+                order_type,
+                max_levels,
+                price,
+                orders.len()
+            );
+            let mut index = self.index(order_type);
+            for order in orders {
+                index.remove(&order.id);
+                self.record(Mutation::Remove {
+                    order_id: order.id,
+                    price,
+                    order_type,
+                });
+            }
+        }
     }
 
     pub async fn clear_orders(&self) {
@@ -37,23 +274,161 @@ impl OrderManager {
         let mut sell_orders = self.sell_orders.write().await;
         buy_orders.clear();
         sell_orders.clear();
+        self.buy_index.lock().unwrap().clear();
+        self.sell_index.lock().unwrap().clear();
+        self.record(Mutation::Clear);
         info!("All orders have been cleared from OrderManager");
     }
 
+    /// Empties the book and asks the feed to resubscribe, so the book is
+    /// rebuilt from what the indexer sends.
+    pub async fn resync(&self) {
+        self.clear_orders().await;
+        self.resync_requested.notify_one();
+    }
+
+    /// Clears the book but for the orders in `keep`, which stay at their
+    /// levels unchanged.
+    pub async fn clear_orders_except(&self, keep: &HashSet<String>) {
+        if keep.is_empty() {
+            self.clear_orders().await;
+            return;
+        }
+        let mut buy_orders = self.buy_orders.write().await;
+        let mut sell_orders = self.sell_orders.write().await;
+        let mut kept = 0;
+        for (order_map, index) in [
+            (&mut *buy_orders, &self.buy_index),
+            (&mut *sell_orders, &self.sell_index),
+        ] {
+            let mut index = index.lock().unwrap();
+            for orders in order_map.values_mut() {
+                for order in orders.iter().filter(|order| !keep.contains(&order.id)) {
+                    index.remove(&order.id);
+                    self.record(Mutation::Remove {
+                        order_id: order.id.clone(),
+                        price: order.price,
+                        order_type: order.order_type,
+                    });
+                }
+                orders.retain(|order| keep.contains(&order.id));
+                kept += orders.len();
+            }
+            order_map.retain(|_, orders| !orders.is_empty());
+        }
+        info!("Cleared OrderManager but for {} held-back order(s)", kept);
+    }
+
+    /// Empties the book and returns what it held, under both write locks so
+    /// no order booked concurrently is lost between the copy and the clear.
+    /// For handing the book over to another instance.
+    pub async fn take_snapshot(&self) -> (Vec<SpotOrder>, Vec<SpotOrder>) {
+        let mut buy_orders = self.buy_orders.write().await;
+        let mut sell_orders = self.sell_orders.write().await;
+        let taken: (Vec<SpotOrder>, Vec<SpotOrder>) = (
+            std::mem::take(&mut *buy_orders)
+                .into_values()
+                .flatten()
+                .collect(),
+            std::mem::take(&mut *sell_orders)
+                .into_values()
+                .flatten()
+                .collect(),
+        );
+        self.buy_index.lock().unwrap().clear();
+        self.sell_index.lock().unwrap().clear();
+        self.record(Mutation::Clear);
+        info!(
+            "Took {} buy and {} sell orders out of OrderManager",
+            taken.0.len(),
+            taken.1.len()
+        );
+        taken
+    }
+
     pub async fn remove_order(&self, order_id: &str, price: u128, order_type: OrderType) {
         let mut order_map = match order_type {
             OrderType::Buy => self.buy_orders.write().await,
             OrderType::Sell => self.sell_orders.write().await,
         };
+        self.record(Mutation::Remove {
+            order_id: order_id.to_string(),
+            price,
+            order_type,
+        });
 
         if let Some(orders) = order_map.get_mut(&price) {
+            let before = orders.len();
             orders.retain(|order| order.id != order_id);
+            if orders.len() < before {
+                self.index(order_type).remove(order_id);
+            }
             if orders.is_empty() {
                 order_map.remove(&price);
             }
         }
     }
 
+    /// Applies one event from an ingest source. Returns `false` if an opened
+    /// order was rejected.
+    pub async fn apply_event(&self, event: OrderEvent) -> bool {
+        match event {
+            OrderEvent::Open(order) => self.add_order(order).await,
+            OrderEvent::Cancel {
+                id,
+                price,
+                order_type,
+            } => {
+                self.touch_ingest();
+                self.remove_order(&id, price, order_type).await;
+                true
+            }
+        }
+    }
+
+    /// Applies submitted matches to the book: each matched order shrinks by
+    /// its filled amount and leaves the book once nothing remains, so fully
+    /// filled orders are never matched again.
+    pub async fn apply_fills(&self, fills: &[MatchRecord]) {
+        let mut buy_fills: HashMap<&str, u128> = HashMap::new();
+        let mut sell_fills: HashMap<&str, u128> = HashMap::new();
+        for fill in fills {
+            let buy = buy_fills.entry(fill.buy_id.as_str()).or_default();
+            *buy = buy.saturating_add(fill.amount);
+            let sell = sell_fills.entry(fill.sell_id.as_str()).or_default();
+            *sell = sell.saturating_add(fill.amount);
+        }
+
+        let mut buy_orders = self.buy_orders.write().await;
+        let mut sell_orders = self.sell_orders.write().await;
+        for (order_map, filled, index) in [
+            (&mut *buy_orders, &buy_fills, &self.buy_index),
+            (&mut *sell_orders, &sell_fills, &self.sell_index),
+        ] {
+            let mut index = index.lock().unwrap();
+            for orders in order_map.values_mut() {
+                for order in orders.iter_mut() {
+                    let Some(amount) = filled.get(order.id.as_str()) else {
+                        continue;
+                    };
+                    order.amount = order.amount.saturating_sub(*amount);
+                    if order.amount == 0 {
+                        index.remove(&order.id);
+                        self.record(Mutation::Remove {
+                            order_id: order.id.clone(),
+                            price: order.price,
+                            order_type: order.order_type,
+                        });
+                    } else {
+                        self.record(Mutation::Add(order.clone()));
+                    }
+                }
+                orders.retain(|order| order.amount > 0);
+            }
+            order_map.retain(|_, orders| !orders.is_empty());
+        }
+    }
+
     pub async fn get_orders(&self, price: u128, order_type: OrderType) -> Vec<SpotOrder> {
         let order_map = match order_type {
             OrderType::Buy => self.buy_orders.read().await,
@@ -73,12 +448,69 @@ impl OrderManager {
         sell_orders.values().cloned().flatten().collect()
     }
 
+    /// Copies both sides under their read locks, so the ids and amounts are
+    /// from one consistent state of the book.
+    pub async fn snapshot(&self) -> BookSnapshot {
+        let buy_orders = self.buy_orders.read().await;
+        let sell_orders = self.sell_orders.read().await;
+        BookSnapshot {
+            taken_at_ms: Utc::now().timestamp_millis(),
+            buy_orders: buy_orders.values().flatten().cloned().collect(),
+            sell_orders: sell_orders.values().flatten().cloned().collect(),
+        }
+    }
+
+    /// Top `depth` levels per side with the sequence of the state they
+    /// were read from.
+    pub async fn l2_snapshot(&self, depth: usize) -> L2Snapshot {
+        let buy_orders = self.buy_orders.read().await;
+        let sell_orders = self.sell_orders.read().await;
+        L2Snapshot {
+            sequence: self.sequence.load(Ordering::Relaxed),
+            bids: aggregate_levels(buy_orders.iter().rev(), depth),
+            asks: aggregate_levels(sell_orders.iter(), depth),
+        }
+    }
+
     pub async fn get_all_orders2(&self) -> (Vec<SpotOrder>, Vec<SpotOrder>) {
         let buy_orders = self.get_all_buy_orders().await;
         let sell_orders = self.get_all_sell_orders().await;
         (buy_orders, sell_orders)
     }
 
+    /// FNV-1a over the `(side, id, price, amount)` tuples of both sides,
+    /// sorted. Independent of map layout and of the Rust version, so
+    /// two instances holding the same book report the same value.
+    pub async fn book_hash(&self) -> u64 {
+        let mut entries: Vec<(u8, String, u128, u128)> = Vec::new();
+        {
+            let buy_orders = self.buy_orders.read().await;
+            let sell_orders = self.sell_orders.read().await;
+            for order in buy_orders.values().flatten() {
+                entries.push((0, order.id.clone(), order.price, order.amount));
+            }
+            for order in sell_orders.values().flatten() {
+                entries.push((1, order.id.clone(), order.price, order.amount));
+            }
+        }
+        entries.sort();
+
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut feed = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for (side, id, price, amount) in &entries {
+            feed(&[*side]);
+            feed(id.as_bytes());
+            feed(&price.to_le_bytes());
+            feed(&amount.to_le_bytes());
+        }
+        hash
+    }
+
     pub async fn log_orders(&self) {
         let buy_orders = self.buy_orders.read().await;
         let sell_orders = self.sell_orders.read().await;
@@ -94,3 +526,437 @@ impl OrderManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::management::limits::MissingTimestampPolicy;
+    use crate::market::crossing::{compute_matches, MatchSettings};
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    fn order(id: &str, order_type: OrderType, amount: u128, price: u128) -> SpotOrder {
+        SpotOrder {
+            id: id.to_string(),
+            user: format!("{}-owner", id),
+            asset: String::new(),
+            amount,
+            price,
+            timestamp: 1,
+            order_type,
+            tag: None,
+            received_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn resync_clears_the_book_and_wakes_the_feed() {
+        let manager = OrderManager::new();
+        assert!(manager.add_order(order("b1", OrderType::Buy, 10, 10)).await);
+
+        manager.resync().await;
+
+        assert!(manager.get_all_buy_orders().await.is_empty());
+        let woken = tokio::time::timeout(
+            Duration::from_millis(10),
+            manager.resync_requested.notified(),
+        )
+        .await;
+        assert!(woken.is_ok(), "resync did not wake the feed");
+    }
+
+    fn fill(buy_id: &str, sell_id: &str, amount: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount,
+            price: 10,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn with_limits(limits: IngestLimits) -> Arc<OrderManager> {
+        OrderManager::with_config(limits, None)
+    }
+
+    #[tokio::test]
+    async fn reingest_update_moves_the_order_and_keeps_when_it_was_booked() {
+        let manager = OrderManager::new();
+        let mut first = order("b1", OrderType::Buy, 10, 10);
+        first.timestamp = 7;
+        first.received_at = 5;
+        assert!(manager.add_order(first).await);
+
+        let mut resent = order("b1", OrderType::Buy, 4, 11);
+        resent.timestamp = 0;
+        assert!(manager.add_order(resent).await);
+
+        let buy_orders = manager.buy_orders.read().await;
+        assert_eq!(buy_orders.keys().collect::<Vec<_>>(), [&11]);
+        let booked = &buy_orders[&11][0];
+        assert_eq!(
+            (booked.amount, booked.timestamp, booked.received_at),
+            (4, 7, 5)
+        );
+    }
+
+    #[tokio::test]
+    async fn reingest_reject_keeps_the_booked_order() {
+        let manager = with_limits(IngestLimits {
+            reingest: ReingestPolicy::Reject,
+            ..Default::default()
+        });
+        assert!(manager.add_order(order("b1", OrderType::Buy, 10, 10)).await);
+        assert!(!manager.add_order(order("b1", OrderType::Buy, 4, 11)).await);
+
+        let buys = manager.get_all_buy_orders().await;
+        assert_eq!(buys.len(), 1);
+        assert_eq!((buys[0].amount, buys[0].price), (10, 10));
+    }
+
+    #[tokio::test]
+    async fn book_hash_ignores_insertion_order_and_tracks_changes() {
+        let book = [
+            order("b1", OrderType::Buy, 10, 10),
+            order("b2", OrderType::Buy, 5, 10),
+            order("s1", OrderType::Sell, 7, 12),
+        ];
+        let forward = OrderManager::new();
+        for order in book.iter().cloned() {
+            forward.add_order(order).await;
+        }
+        let backward = OrderManager::new();
+        for order in book.iter().rev().cloned() {
+            backward.add_order(order).await;
+        }
+        let hash = forward.book_hash().await;
+        assert_eq!(hash, backward.book_hash().await);
+
+        forward.add_order(order("b2", OrderType::Buy, 4, 10)).await;
+        let resized = forward.book_hash().await;
+        assert_ne!(resized, hash);
+
+        forward.remove_order("s1", 12, OrderType::Sell).await;
+        assert_ne!(forward.book_hash().await, resized);
+    }
+
+    #[tokio::test]
+    async fn events_open_and_cancel_orders() {
+        let manager = OrderManager::new();
+        assert!(
+            manager
+                .apply_event(OrderEvent::Open(order("b1", OrderType::Buy, 10, 10)))
+                .await
+        );
+        assert!(
+            manager
+                .apply_event(OrderEvent::Open(order("b2", OrderType::Buy, 5, 10)))
+                .await
+        );
+
+        assert!(
+            manager
+                .apply_event(OrderEvent::Cancel {
+                    id: "b1".to_string(),
+                    price: 10,
+                    order_type: OrderType::Buy,
+                })
+                .await
+        );
+
+        let buys = manager.get_all_buy_orders().await;
+        assert_eq!(
+            buys.iter()
+                .map(|order| order.id.as_str())
+                .collect::<Vec<_>>(),
+            ["b2"]
+        );
+        assert!(manager.feed_lag().is_some());
+    }
+
+    #[tokio::test]
+    async fn out_of_bounds_prices_never_reach_the_book() {
+        let manager = with_limits(IngestLimits {
+            max_buy_price: Some(100),
+            min_sell_price: Some(10),
+            ..Default::default()
+        });
+        assert!(
+            !manager
+                .add_order(order("b1", OrderType::Buy, 1, u128::MAX))
+                .await
+        );
+        assert!(!manager.add_order(order("s1", OrderType::Sell, 1, 1)).await);
+        assert!(manager.add_order(order("b2", OrderType::Buy, 1, 100)).await);
+
+        let (buys, sells) = manager.get_all_orders2().await;
+        assert_eq!((buys.len(), sells.len()), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn dust_below_the_minimum_amount_is_not_booked() {
+        let manager = with_limits(IngestLimits {
+            min_order_amount: Some(1_000),
+            ..Default::default()
+        });
+        assert!(
+            !manager
+                .add_order(order("b1", OrderType::Buy, 999, 10))
+                .await
+        );
+        assert!(manager.get_all_buy_orders().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_batch_books_the_valid_orders_and_counts_the_rest() {
+        let manager = with_limits(IngestLimits {
+            max_buy_price: Some(20),
+            reingest: ReingestPolicy::Reject,
+            ..Default::default()
+        });
+        let (accepted, rejected) = manager
+            .add_orders(vec![
+                order("b1", OrderType::Buy, 10, 10),
+                order("b2", OrderType::Buy, 0, 10),
+                order("b3", OrderType::Buy, 5, 25),
+                order("s1", OrderType::Sell, 7, 12),
+                order("b1", OrderType::Buy, 4, 11),
+            ])
+            .await;
+        assert_eq!((accepted, rejected), (2, 3));
+
+        let (buys, sells) = manager.get_all_orders2().await;
+        assert_eq!(
+            buys.iter()
+                .map(|order| (order.id.as_str(), order.amount))
+                .collect::<Vec<_>>(),
+            [("b1", 10)]
+        );
+        assert_eq!(sells.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn levels_past_the_cap_are_evicted_farthest_first() {
+        let manager = with_limits(IngestLimits {
+            max_book_levels: Some(2),
+            ..Default::default()
+        });
+        let (accepted, rejected) = manager
+            .add_orders(vec![
+                order("b7", OrderType::Buy, 1, 7),
+                order("b8", OrderType::Buy, 1, 8),
+                order("b9", OrderType::Buy, 1, 9),
+                order("s12", OrderType::Sell, 1, 12),
+                order("s10", OrderType::Sell, 1, 10),
+                order("s11", OrderType::Sell, 1, 11),
+            ])
+            .await;
+        assert_eq!((accepted, rejected), (6, 0));
+        // Farther than every booked level, so evicted as soon as it is booked.
+        assert!(!manager.add_order(order("b6", OrderType::Buy, 1, 6)).await);
+
+        let prices = |orders: Vec<SpotOrder>| -> Vec<u128> {
+            let mut prices: Vec<u128> = orders.iter().map(|order| order.price).collect();
+            prices.sort();
+            prices
+        };
+        assert_eq!(prices(manager.get_all_buy_orders().await), [8, 9]);
+        assert_eq!(prices(manager.get_all_sell_orders().await), [10, 11]);
+        assert!(!manager.buy_index.lock().unwrap().contains_key("b7"));
+    }
+
+    #[tokio::test]
+    async fn fills_shrink_orders_and_remove_the_filled_ones() {
+        let manager = OrderManager::new();
+        manager
+            .add_orders(vec![
+                order("b1", OrderType::Buy, 100, 10),
+                order("s1", OrderType::Sell, 30, 10),
+                order("s2", OrderType::Sell, 50, 10),
+            ])
+            .await;
+
+        manager
+            .apply_fills(&[fill("b1", "s1", 30), fill("b1", "s2", 20)])
+            .await;
+
+        let buys = manager.get_all_buy_orders().await;
+        assert_eq!(buys.len(), 1);
+        assert_eq!(buys[0].amount, 50);
+        let sells = manager.get_all_sell_orders().await;
+        assert_eq!(sells.len(), 1);
+        assert_eq!((sells[0].id.as_str(), sells[0].amount), ("s2", 30));
+
+        manager.apply_fills(&[fill("b1", "s2", 30)]).await;
+        assert_eq!(manager.get_all_buy_orders().await[0].amount, 20);
+        assert!(manager.get_all_sell_orders().await.is_empty());
+        assert!(manager.sell_orders.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_capped_cycle_leaves_the_deferred_orders_on_the_book() {
+        let manager = OrderManager::new();
+        manager
+            .add_orders(vec![
+                order("b1", OrderType::Buy, 100, 12),
+                order("s1", OrderType::Sell, 30, 10),
+                order("s2", OrderType::Sell, 30, 11),
+            ])
+            .await;
+
+        let (buys, sells) = manager.get_all_orders2().await;
+        let settings = MatchSettings {
+            max_cycle_notional: Some(500),
+            ..Default::default()
+        };
+        let outcome = compute_matches(
+            BinaryHeap::from(buys),
+            sells.into_iter().map(Reverse).collect(),
+            &settings,
+        );
+        manager.apply_fills(&outcome.matches).await;
+
+        let buys = manager.get_all_buy_orders().await;
+        assert_eq!(buys.len(), 1);
+        assert_eq!(buys[0].amount, 70);
+        let sells = manager.get_all_sell_orders().await;
+        assert_eq!(
+            sells.iter().map(|o| o.id.as_str()).collect::<Vec<_>>(),
+            ["s2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn book_history_keeps_the_best_depth_levels_per_side() {
+        let manager = OrderManager::new();
+        manager
+            .add_orders(vec![
+                order("b1", OrderType::Buy, 5, 8),
+                order("b2", OrderType::Buy, 7, 9),
+                order("b3", OrderType::Buy, 3, 9),
+                order("b4", OrderType::Buy, 4, 7),
+                order("s1", OrderType::Sell, 6, 11),
+                order("s2", OrderType::Sell, 2, 10),
+            ])
+            .await;
+
+        let snapshot = manager.l2_snapshot(2).await;
+        let level = |price: &str, quantity: &str| [price.to_string(), quantity.to_string()];
+        assert_eq!(snapshot.bids, vec![level("9", "10"), level("8", "5")]);
+        assert_eq!(snapshot.asks, vec![level("10", "2"), level("11", "6")]);
+    }
+
+    #[tokio::test]
+    async fn l2_snapshots_carry_the_sequence_of_the_book_they_read() {
+        let manager = OrderManager::new();
+        let empty = manager.l2_snapshot(5).await;
+        assert_eq!(empty.sequence, 0);
+        assert!(empty.bids.is_empty() && empty.asks.is_empty());
+
+        manager.add_order(order("b1", OrderType::Buy, 5, 9)).await;
+        manager.add_order(order("s1", OrderType::Sell, 3, 11)).await;
+        assert_eq!(manager.l2_snapshot(5).await.sequence, 2);
+        assert_eq!(manager.l2_snapshot(5).await.sequence, 2);
+
+        manager.remove_order("b1", 9, OrderType::Buy).await;
+        let snapshot = manager.l2_snapshot(5).await;
+        assert_eq!(snapshot.sequence, 3);
+        assert!(snapshot.bids.is_empty());
+        assert_eq!(snapshot.asks, vec![["11".to_string(), "3".to_string()]]);
+
+        manager.clear_orders().await;
+        assert_eq!(manager.l2_snapshot(5).await.sequence, 4);
+    }
+
+    #[tokio::test]
+    async fn take_snapshot_hands_off_every_order_exactly_once() {
+        let manager = OrderManager::new();
+        let inserting = {
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                for n in 0..200u128 {
+                    let order_type = if n % 2 == 0 {
+                        OrderType::Buy
+                    } else {
+                        OrderType::Sell
+                    };
+                    let id = format!("o{}", n);
+                    assert!(
+                        manager
+                            .add_order(order(&id, order_type, 1, 10 + n % 7))
+                            .await
+                    );
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let mut handed_off = Vec::new();
+        let mut hand_off = |(buys, sells): (Vec<SpotOrder>, Vec<SpotOrder>)| {
+            handed_off.extend(buys.into_iter().chain(sells).map(|order| order.id));
+        };
+        while !inserting.is_finished() {
+            hand_off(manager.take_snapshot().await);
+            tokio::task::yield_now().await;
+        }
+        inserting.await.unwrap();
+        hand_off(manager.take_snapshot().await);
+
+        assert_eq!(handed_off.len(), 200);
+        let unique: HashSet<&String> = handed_off.iter().collect();
+        assert_eq!(unique.len(), 200);
+        assert!(manager.get_all_buy_orders().await.is_empty());
+        assert!(manager.get_all_sell_orders().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn timestamp_less_orders_get_the_ingest_time_or_are_rejected() {
+        let unstamped = |id: &str| SpotOrder {
+            timestamp: 0,
+            ..order(id, OrderType::Sell, 5, 10)
+        };
+
+        let manager = OrderManager::new();
+        let before = Utc::now().timestamp() as u64;
+        assert!(manager.add_order(unstamped("s1")).await);
+        assert_eq!(manager.add_orders(vec![unstamped("s2")]).await, (1, 0));
+        for booked in manager.get_all_sell_orders().await {
+            assert!(booked.timestamp >= before, "{:?}", booked);
+        }
+
+        let manager = with_limits(IngestLimits {
+            missing_timestamp: MissingTimestampPolicy::Reject,
+            ..Default::default()
+        });
+        assert!(!manager.add_order(unstamped("s1")).await);
+        assert_eq!(
+            manager
+                .add_orders(vec![unstamped("s2"), order("s3", OrderType::Sell, 5, 10)])
+                .await,
+            (1, 1)
+        );
+        let booked: Vec<String> = manager
+            .get_all_sell_orders()
+            .await
+            .into_iter()
+            .map(|order| order.id)
+            .collect();
+        assert_eq!(booked, ["s3"]);
+    }
+
+    #[tokio::test]
+    async fn feed_lag_counts_from_the_last_streamed_order() {
+        let manager = OrderManager::new();
+        assert_eq!(manager.feed_lag(), None);
+
+        manager.add_order(order("b1", OrderType::Buy, 0, 10)).await;
+        assert!(manager.feed_lag().unwrap() < Duration::from_secs(1));
+
+        let five_seconds_ago = Utc::now().timestamp_millis() - 5_000;
+        manager
+            .last_ingest_ms
+            .store(five_seconds_ago, Ordering::Relaxed);
+        assert!(manager.feed_lag().unwrap() >= Duration::from_secs(5));
+    }
+}
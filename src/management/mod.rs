@@ -1 +1,7 @@
+pub mod consistency;
+pub mod depth;
+pub mod limits;
 pub mod manager;
+pub mod mutation_log;
+pub mod rate_limit;
+pub mod snapshot;
@@ -0,0 +1,63 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::model::{OrderType, SpotOrder};
+
+/// One mutating call on `OrderManager`, in the order it was applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Mutation {
+    Add(SpotOrder),
+    Remove {
+        order_id: String,
+        price: u128,
+        order_type: OrderType,
+    },
+    Clear,
+}
+
+/// Append-only JSON-lines record of book mutations. Replaying a file from the
+/// start rebuilds the book as it was after the last recorded line.
+pub struct MutationLog {
+    file: Mutex<File>,
+}
+
+impl MutationLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, mutation: &Mutation) {
+        let line = match serde_json::to_string(mutation) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize mutation {:?}: {:?}", mutation, e);
+                return;
+            }
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            error!("Failed to append to mutation log: {:?}", e);
+        }
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Mutation>, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut mutations = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            mutations.push(serde_json::from_str(&line)?);
+        }
+        Ok(mutations)
+    }
+}
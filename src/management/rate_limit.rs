@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::time::Instant;
+
+/// Orders a single user may submit: `rate` per second on average, with
+/// bursts of up to `burst` at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OwnerRateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+/// One token bucket per user. Buckets start full and are never dropped, so
+/// memory grows with the number of distinct users seen.
+#[derive(Debug)]
+pub struct OwnerRateLimiter {
+    pub limit: OwnerRateLimit,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl OwnerRateLimiter {
+    pub fn new(limit: OwnerRateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from `owner`'s bucket, returning `false` if it is empty.
+    pub fn allow(&self, owner: &str) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let (tokens, refilled_at) = buckets
+            .entry(owner.to_string())
+            .or_insert((self.limit.burst, now));
+
+        let elapsed = now.duration_since(*refilled_at).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        *refilled_at = now;
+
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter(rate: f64, burst: f64) -> OwnerRateLimiter {
+        OwnerRateLimiter::new(OwnerRateLimit { rate, burst })
+    }
+
+    #[test]
+    fn allows_a_burst_then_rejects() {
+        let limiter = limiter(1.0, 3.0);
+        assert!(limiter.allow("alice"));
+        assert!(limiter.allow("alice"));
+        assert!(limiter.allow("alice"));
+        assert!(!limiter.allow("alice"));
+        // Each user has a bucket of their own.
+        assert!(limiter.allow("bob"));
+    }
+
+    #[test]
+    fn refills_at_the_rate_up_to_the_burst() {
+        let limiter = limiter(1000.0, 2.0);
+        assert!(limiter.allow("alice"));
+        assert!(limiter.allow("alice"));
+        assert!(!limiter.allow("alice"));
+
+        // Long enough for far more than `burst` tokens at this rate.
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.allow("alice"));
+        assert!(limiter.allow("alice"));
+        assert!(!limiter.allow("alice"));
+    }
+}
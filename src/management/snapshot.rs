@@ -0,0 +1,349 @@
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::manager::OrderManager;
+use crate::error::Error;
+use crate::model::{OrderType, SpotOrder};
+
+/// Both sides of the book at one instant, written to disk so a restarted
+/// matcher does not start blind while the feed catches up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    /// Unix time in milliseconds.
+    pub taken_at_ms: i64,
+    pub buy_orders: Vec<SpotOrder>,
+    pub sell_orders: Vec<SpotOrder>,
+}
+
+/// An order present in both snapshots whose amount or price differs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrderChange {
+    pub id: String,
+    pub order_type: OrderType,
+    pub before_amount: u128,
+    pub after_amount: u128,
+    pub before_price: u128,
+    pub after_price: u128,
+}
+
+/// What changed between two snapshots, by order id, each list sorted by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BookDiff {
+    pub added: Vec<SpotOrder>,
+    pub removed: Vec<SpotOrder>,
+    pub changed: Vec<OrderChange>,
+}
+
+impl BookDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl BookSnapshot {
+    /// Compares this snapshot with a later one, e.g. the local book with
+    /// one read from chain. An id that switched sides counts as removed
+    /// from one side and added to the other.
+    pub fn diff(&self, after: &BookSnapshot) -> BookDiff {
+        let before = self.by_id();
+        let mut after = after.by_id();
+
+        let mut diff = BookDiff::default();
+        for (key, old) in before {
+            match after.remove(&key) {
+                Some(new) if new.amount != old.amount || new.price != old.price => {
+                    diff.changed.push(OrderChange {
+                        id: old.id,
+                        order_type: old.order_type,
+                        before_amount: old.amount,
+                        after_amount: new.amount,
+                        before_price: old.price,
+                        after_price: new.price,
+                    })
+                }
+                Some(_) => {}
+                None => diff.removed.push(old),
+            }
+        }
+        diff.added.extend(after.into_values());
+
+        diff.added.sort_by(|a, b| a.id.cmp(&b.id));
+        diff.removed.sort_by(|a, b| a.id.cmp(&b.id));
+        diff.changed.sort_by(|a, b| a.id.cmp(&b.id));
+        diff
+    }
+
+    fn by_id(&self) -> HashMap<(String, OrderType), SpotOrder> {
+        self.buy_orders
+            .iter()
+            .chain(&self.sell_orders)
+            .map(|order| ((order.id.clone(), order.order_type), order.clone()))
+            .collect()
+    }
+
+    pub fn age(&self) -> Duration {
+        let age_ms = Utc::now().timestamp_millis() - self.taken_at_ms;
+        Duration::from_millis(age_ms.max(0) as u64)
+    }
+
+    /// Writes to a sibling temp file and renames it over `path`, so a crash
+    /// mid-write never leaves a truncated snapshot behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+        }
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// What to do when a restored book is already crossed, which a stale
+/// snapshot can cause and which the first cycle would match in bulk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrossedBookPolicy {
+    /// Match it like any other book.
+    #[default]
+    Match,
+    /// Pause matching until it is resumed through `POST /resume`.
+    Pause,
+}
+
+impl FromStr for CrossedBookPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Match" => Ok(CrossedBookPolicy::Match),
+            "Pause" => Ok(CrossedBookPolicy::Pause),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown crossed book policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Whether the best bid is more than `threshold` above the best ask, logging
+/// the overlap if so.
+pub async fn is_crossed_beyond(order_manager: &OrderManager, threshold: u128) -> bool {
+    let best_bid = order_manager
+        .buy_orders
+        .read()
+        .await
+        .keys()
+        .next_back()
+        .copied();
+    let best_ask = order_manager
+        .sell_orders
+        .read()
+        .await
+        .keys()
+        .next()
+        .copied();
+    match (best_bid, best_ask) {
+        (Some(bid), Some(ask)) if bid > ask && bid - ask > threshold => {
+            warn!(
+                "Restored book is crossed: best bid {} is {} above best ask {} (threshold {})",
+                bid,
+                bid - ask,
+                ask,
+                threshold
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Loads the snapshot at `path` into the book unless it is missing or older
+/// than `max_age`. Returns the number of orders restored.
+pub async fn restore_book(
+    order_manager: &OrderManager,
+    path: &Path,
+    max_age: Duration,
+) -> Result<usize, Error> {
+    if !path.exists() {
+        info!("No book snapshot at {}, starting empty", path.display());
+        return Ok(0);
+    }
+
+    let snapshot = BookSnapshot::load(path)?;
+    let age = snapshot.age();
+    if age > max_age {
+        info!(
+            "Book snapshot at {} is {:?} old (max {:?}), starting empty",
+            path.display(),
+            age,
+            max_age
+        );
+        return Ok(0);
+    }
+
+    let orders: Vec<SpotOrder> = snapshot
+        .buy_orders
+        .into_iter()
+        .chain(snapshot.sell_orders)
+        .collect();
+    let (accepted, rejected) = order_manager.add_orders(orders).await;
+    info!(
+        "Restored {} orders from a {:?} old snapshot ({} rejected)",
+        accepted, age, rejected
+    );
+    Ok(accepted)
+}
+
+/// Writes a snapshot of the book to `path` every `interval`.
+pub async fn persist_book(order_manager: Arc<OrderManager>, path: PathBuf, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let snapshot = order_manager.snapshot().await;
+        if let Err(e) = snapshot.save(&path) {
+            error!(
+                "Failed to write book snapshot to {}: {:?}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: &str, order_type: OrderType, amount: u128, price: u128) -> SpotOrder {
+        SpotOrder {
+            id: id.to_string(),
+            user: format!("{}-owner", id),
+            asset: String::new(),
+            amount,
+            price,
+            timestamp: 1,
+            order_type,
+            tag: None,
+            received_at: 0,
+        }
+    }
+
+    fn snapshot(orders: Vec<SpotOrder>) -> BookSnapshot {
+        let (buy_orders, sell_orders) = orders
+            .into_iter()
+            .partition(|order| order.order_type == OrderType::Buy);
+        BookSnapshot {
+            taken_at_ms: 1_700_000_000_000,
+            buy_orders,
+            sell_orders,
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = std::env::temp_dir().join(format!("spark-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("book.json");
+
+        let mut tagged = order("s1", OrderType::Sell, 5, 11);
+        tagged.tag = Some("mm".to_string());
+        tagged.received_at = 1_700_000_000_123;
+        let saved = snapshot(vec![order("b1", OrderType::Buy, 10, 9), tagged]);
+        saved.save(&path).unwrap();
+        let loaded = BookSnapshot::load(&path);
+        let tmp_left = path.with_extension("tmp").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // `SpotOrder` equality only looks at the price, so compare every field.
+        let fields = |snapshot: &BookSnapshot| -> Vec<String> {
+            snapshot
+                .buy_orders
+                .iter()
+                .chain(&snapshot.sell_orders)
+                .map(|order| format!("{:?}", order))
+                .collect()
+        };
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.taken_at_ms, saved.taken_at_ms);
+        assert_eq!(fields(&loaded), fields(&saved));
+        assert!(!tmp_left);
+    }
+
+    #[test]
+    fn loading_a_missing_snapshot_fails() {
+        let path = std::env::temp_dir().join("spark-snapshot-that-does-not-exist.json");
+        assert!(BookSnapshot::load(path).is_err());
+    }
+
+    #[test]
+    fn diff_sorts_added_removed_and_changed_by_id() {
+        let before = snapshot(vec![
+            order("b1", OrderType::Buy, 10, 9),
+            order("b2", OrderType::Buy, 10, 9),
+            order("s1", OrderType::Sell, 10, 11),
+            order("s2", OrderType::Sell, 10, 11),
+        ]);
+        let after = snapshot(vec![
+            order("b1", OrderType::Buy, 10, 9),
+            order("b2", OrderType::Buy, 4, 9),
+            order("s2", OrderType::Sell, 10, 12),
+            order("s4", OrderType::Sell, 1, 13),
+            order("s3", OrderType::Sell, 1, 13),
+        ]);
+
+        let diff = before.diff(&after);
+        let ids = |orders: &[SpotOrder]| -> Vec<String> {
+            orders.iter().map(|order| order.id.clone()).collect()
+        };
+        assert_eq!(ids(&diff.added), ["s3", "s4"]);
+        assert_eq!(ids(&diff.removed), ["s1"]);
+        assert_eq!(
+            diff.changed,
+            vec![
+                OrderChange {
+                    id: "b2".to_string(),
+                    order_type: OrderType::Buy,
+                    before_amount: 10,
+                    after_amount: 4,
+                    before_price: 9,
+                    after_price: 9,
+                },
+                OrderChange {
+                    id: "s2".to_string(),
+                    order_type: OrderType::Sell,
+                    before_amount: 10,
+                    after_amount: 10,
+                    before_price: 11,
+                    after_price: 12,
+                },
+            ]
+        );
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn an_order_that_switched_sides_is_removed_and_added() {
+        let before = snapshot(vec![order("o1", OrderType::Buy, 10, 9)]);
+        let after = snapshot(vec![order("o1", OrderType::Sell, 10, 9)]);
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.removed[0].order_type, OrderType::Buy);
+        assert_eq!(diff.added[0].order_type, OrderType::Sell);
+        assert!(diff.changed.is_empty());
+    }
+}
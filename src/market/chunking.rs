@@ -0,0 +1,280 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::model::MatchRecord;
+
+/// How the cycle's matches are split into `match_order_many` calls.
+///
+/// `Count` cuts the list every `MATCH_CHUNK_SIZE` matches (one call when the
+/// size is unset). `PriceLevel` starts a new chunk whenever the sell price of
+/// consecutive matches changes, so each call sweeps exactly one ask level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkBy {
+    #[default]
+    Count,
+    PriceLevel,
+}
+
+impl FromStr for ChunkBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Count" => Ok(ChunkBy::Count),
+            "PriceLevel" => Ok(ChunkBy::PriceLevel),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown chunk mode {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// The order in which a cycle's chunks are submitted.
+///
+/// `Crossing` keeps the order the matches were crossed in. `Notional` and
+/// `Amount` submit the largest chunks by `amount * price` or by amount
+/// first, so the most valuable matches are in flight before any later chunk
+/// can fail or the cycle budget runs out. Sorting overrides the retry queue's
+/// head start for orders of previously failed chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkOrder {
+    #[default]
+    Crossing,
+    Notional,
+    Amount,
+}
+
+impl FromStr for ChunkOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Crossing" => Ok(ChunkOrder::Crossing),
+            "Notional" => Ok(ChunkOrder::Notional),
+            "Amount" => Ok(ChunkOrder::Amount),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown chunk order {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Sorts chunks into submission order; the sort is stable, so equal chunks
+/// keep their crossing order.
+pub fn order_chunks(chunks: &mut [&[MatchRecord]], order: ChunkOrder) {
+    let key = |chunk: &[MatchRecord]| -> u128 {
+        match order {
+            ChunkOrder::Crossing => 0,
+            ChunkOrder::Notional => chunk.iter().fold(0u128, |total, record| {
+                total.saturating_add(record.amount.saturating_mul(record.price))
+            }),
+            ChunkOrder::Amount => chunk
+                .iter()
+                .map(|record| record.amount)
+                .fold(0, u128::saturating_add),
+        }
+    };
+    if order != ChunkOrder::Crossing {
+        chunks.sort_by_key(|chunk| std::cmp::Reverse(key(chunk)));
+    }
+}
+
+/// Moves the matches that involve any of `order_ids` to the front, keeping
+/// the crossing order within both groups.
+pub fn prioritize(matches: Vec<MatchRecord>, order_ids: &HashSet<String>) -> Vec<MatchRecord> {
+    let (mut first, rest): (Vec<_>, Vec<_>) = matches.into_iter().partition(|record| {
+        order_ids.contains(&record.buy_id) || order_ids.contains(&record.sell_id)
+    });
+    first.extend(rest);
+    first
+}
+
+/// Splits the matches without reordering them.
+pub fn chunk_matches(
+    matches: &[MatchRecord],
+    chunk_by: ChunkBy,
+    chunk_size: Option<usize>,
+) -> Vec<&[MatchRecord]> {
+    if matches.is_empty() {
+        return Vec::new();
+    }
+
+    match chunk_by {
+        ChunkBy::Count => match chunk_size {
+            Some(size) if size > 0 => matches.chunks(size).collect(),
+            _ => vec![matches],
+        },
+        ChunkBy::PriceLevel => {
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            for i in 1..matches.len() {
+                if matches[i].price != matches[start].price {
+                    chunks.push(&matches[start..i]);
+                    start = i;
+                }
+            }
+            chunks.push(&matches[start..]);
+            chunks
+        }
+    }
+}
+
+/// Splits chunks further so no call carries more than `max_amount` in total
+/// or more than `max_ids` unique order ids. A single match over either cap
+/// cannot be split and goes out as a chunk of its own.
+pub fn cap_chunks(
+    chunks: Vec<&[MatchRecord]>,
+    max_amount: Option<u128>,
+    max_ids: Option<usize>,
+) -> Vec<&[MatchRecord]> {
+    if max_amount.is_none() && max_ids.is_none() {
+        return chunks;
+    }
+
+    let mut capped = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        let mut start = 0;
+        let mut amount: u128 = 0;
+        let mut ids: HashSet<&str> = HashSet::new();
+        for (i, record) in chunk.iter().enumerate() {
+            let next_amount = amount.saturating_add(record.amount);
+            let new_ids = [record.buy_id.as_str(), record.sell_id.as_str()]
+                .iter()
+                .filter(|id| !ids.contains(*id))
+                .count();
+            let over = max_amount.is_some_and(|max| next_amount > max)
+                || max_ids.is_some_and(|max| ids.len() + new_ids > max);
+            if over && i > start {
+                capped.push(&chunk[start..i]);
+                start = i;
+                amount = 0;
+                ids.clear();
+            }
+            amount = amount.saturating_add(record.amount);
+            ids.insert(record.buy_id.as_str());
+            ids.insert(record.sell_id.as_str());
+        }
+        capped.push(&chunk[start..]);
+    }
+    capped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(buy_id: &str, sell_id: &str, amount: u128, price: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount,
+            price,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn sizes(chunks: &[&[MatchRecord]]) -> Vec<usize> {
+        chunks.iter().map(|chunk| chunk.len()).collect()
+    }
+
+    #[test]
+    fn chunks_by_count_or_price_level() {
+        let matches = [
+            record("b1", "s1", 5, 10),
+            record("b1", "s2", 5, 10),
+            record("b1", "s3", 5, 11),
+            record("b2", "s4", 5, 12),
+            record("b2", "s5", 5, 12),
+        ];
+        assert_eq!(sizes(&chunk_matches(&matches, ChunkBy::Count, None)), [5]);
+        assert_eq!(
+            sizes(&chunk_matches(&matches, ChunkBy::Count, Some(2))),
+            [2, 2, 1]
+        );
+
+        let levels = chunk_matches(&matches, ChunkBy::PriceLevel, Some(2));
+        assert_eq!(sizes(&levels), [2, 1, 2]);
+        for chunk in &levels {
+            assert!(chunk.iter().all(|record| record.price == chunk[0].price));
+        }
+        assert!(chunk_matches(&[], ChunkBy::PriceLevel, None).is_empty());
+    }
+
+    #[test]
+    fn orders_chunks_largest_first_and_keeps_ties_in_crossing_order() {
+        let matches = [
+            record("b1", "s1", 10, 5),
+            record("b2", "s2", 2, 100),
+            record("b3", "s3", 10, 5),
+            record("b4", "s4", 4, 1),
+        ];
+        let singles = || -> Vec<&[MatchRecord]> { matches.chunks(1).collect() };
+        let first_buys = |chunks: Vec<&[MatchRecord]>| -> Vec<String> {
+            chunks.iter().map(|chunk| chunk[0].buy_id.clone()).collect()
+        };
+
+        let mut chunks = singles();
+        order_chunks(&mut chunks, ChunkOrder::Crossing);
+        assert_eq!(first_buys(chunks), ["b1", "b2", "b3", "b4"]);
+
+        let mut chunks = singles();
+        order_chunks(&mut chunks, ChunkOrder::Notional);
+        assert_eq!(first_buys(chunks), ["b2", "b1", "b3", "b4"]);
+
+        let mut chunks = singles();
+        order_chunks(&mut chunks, ChunkOrder::Amount);
+        assert_eq!(first_buys(chunks), ["b1", "b3", "b4", "b2"]);
+    }
+
+    #[test]
+    fn chunk_keys_saturate_instead_of_overflowing() {
+        let huge = [record("b1", "s1", u128::MAX, 2), record("b1", "s2", 1, 2)];
+        let small = [record("b2", "s3", 3, 1)];
+        for order in [ChunkOrder::Notional, ChunkOrder::Amount] {
+            let mut chunks: Vec<&[MatchRecord]> = vec![&small, &huge];
+            order_chunks(&mut chunks, order);
+            assert_eq!(chunks[0][0].buy_id, "b1");
+        }
+    }
+
+    #[test]
+    fn caps_split_chunks_on_total_amount() {
+        let matches = [
+            record("b1", "s1", 40, 10),
+            record("b1", "s2", 40, 10),
+            record("b1", "s3", 40, 10),
+            record("b2", "s4", 200, 10),
+            record("b3", "s5", 10, 10),
+        ];
+        assert_eq!(sizes(&cap_chunks(vec![&matches], None, None)), [5]);
+        // A single match over the cap goes out alone.
+        assert_eq!(
+            sizes(&cap_chunks(vec![&matches], Some(100), None)),
+            [2, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn caps_split_chunks_on_unique_order_ids() {
+        let matches = [
+            record("b1", "s1", 1, 10),
+            record("b1", "s2", 1, 10),
+            record("b2", "s2", 1, 10),
+            record("b3", "s3", 1, 10),
+        ];
+        // b1, shared by the first two matches, counts once: b1, s1, s2.
+        assert_eq!(sizes(&cap_chunks(vec![&matches], None, Some(3))), [2, 1, 1]);
+        assert_eq!(sizes(&cap_chunks(vec![&matches], None, Some(4))), [3, 1]);
+    }
+
+    #[test]
+    fn caps_never_merge_chunks() {
+        let matches = [record("b1", "s1", 1, 10), record("b2", "s2", 1, 11)];
+        let chunks = chunk_matches(&matches, ChunkBy::PriceLevel, None);
+        assert_eq!(sizes(&cap_chunks(chunks, Some(100), Some(100))), [1, 1]);
+    }
+}
@@ -0,0 +1,192 @@
+use fuels::accounts::provider::Provider;
+use fuels::types::tx_status::TxStatus;
+use fuels::types::Bytes32;
+use log::warn;
+use std::str::FromStr;
+use tokio::time::{Duration, Instant};
+
+use crate::error::Error;
+
+/// What to do with the book once a chunk's inclusion was rolled back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReorgPolicy {
+    /// Keep the chunk's orders, leaving the rest of the book to the feed's
+    /// usual updates.
+    #[default]
+    Keep,
+    /// Clear the book and have the feed resubscribe, so it is rebuilt from
+    /// the indexer's view of the chain after the rollback.
+    Resync,
+}
+
+impl FromStr for ReorgPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Keep" => Ok(ReorgPolicy::Keep),
+            "Resync" => Ok(ReorgPolicy::Resync),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown reorg policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// How long to wait for a submitted chunk to land before calling it matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmationWait {
+    /// Blocks that must follow the one including the transaction; 0 accepts
+    /// the including block itself.
+    pub depth: u32,
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+    pub on_reorg: ReorgPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Confirmation {
+    Confirmed,
+    /// The node reported the transaction as reverted or squeezed out.
+    Failed(String),
+    /// Neither confirmed nor failed before the timeout.
+    Uncertain,
+    /// The block that included the transaction at this height is no longer
+    /// on the chain, so the inclusion was rolled back.
+    Reorged {
+        included_at: u32,
+    },
+}
+
+/// The block a transaction was included in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Inclusion {
+    block_id: Bytes32,
+    height: u32,
+}
+
+/// Polls `provider` until `tx_id` is `depth` blocks deep, has failed, its
+/// including block has been rolled back, or `wait.timeout` runs out. The
+/// inclusion is checked again on every poll of the depth wait. Polling
+/// errors are logged and retried.
+pub async fn wait_for_confirmation(
+    provider: &Provider,
+    tx_id: &Bytes32,
+    wait: &ConfirmationWait,
+) -> Confirmation {
+    let deadline = Instant::now() + wait.timeout;
+    let mut included: Option<Inclusion> = None;
+
+    loop {
+        match included {
+            Some(included) => match (
+                inclusion(provider, tx_id).await,
+                provider.latest_block_height().await,
+            ) {
+                (Ok(current), Ok(latest)) => {
+                    if let Some(confirmation) = judge(&included, current, latest, wait.depth) {
+                        return confirmation;
+                    }
+                }
+                (Err(e), _) => warn!("Failed to read the block of tx {}: {}", tx_id, e),
+                (_, Err(e)) => warn!("Failed to read block height for tx {}: {}", tx_id, e),
+            },
+            None => match provider.tx_status(tx_id).await {
+                Ok(TxStatus::Success { .. }) if wait.depth == 0 => return Confirmation::Confirmed,
+                Ok(TxStatus::Success { .. }) => match inclusion(provider, tx_id).await {
+                    Ok(Some(inclusion)) => included = Some(inclusion),
+                    Ok(None) => warn!("Tx {} succeeded but its block is not known yet", tx_id),
+                    Err(e) => warn!("Failed to read the block of tx {}: {}", tx_id, e),
+                },
+                Ok(TxStatus::Revert { reason, .. }) => return Confirmation::Failed(reason),
+                Ok(TxStatus::SqueezedOut { reason }) => return Confirmation::Failed(reason),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to read status of tx {}: {}", tx_id, e),
+            },
+        }
+
+        if Instant::now() + wait.poll_interval > deadline {
+            return Confirmation::Uncertain;
+        }
+        tokio::time::sleep(wait.poll_interval).await;
+    }
+}
+
+/// The block `tx_id` is included in, `None` while the node knows of no such
+/// block.
+async fn inclusion(provider: &Provider, tx_id: &Bytes32) -> Result<Option<Inclusion>, String> {
+    let response = provider
+        .get_transaction_by_id(tx_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    let block_id = match response.and_then(|response| response.block_id) {
+        Some(block_id) => block_id,
+        None => return Ok(None),
+    };
+    let block = provider.block(&block_id).await.map_err(|e| e.to_string())?;
+    Ok(block.map(|block| Inclusion {
+        block_id,
+        height: block.header.height,
+    }))
+}
+
+/// One poll of the depth wait for a transaction first seen in `included`,
+/// given where it is included now and the latest chain height. `None` keeps
+/// waiting.
+fn judge(
+    included: &Inclusion,
+    current: Option<Inclusion>,
+    latest: u32,
+    depth: u32,
+) -> Option<Confirmation> {
+    if current.as_ref() != Some(included) || latest < included.height {
+        return Some(Confirmation::Reorged {
+            included_at: included.height,
+        });
+    }
+    if latest >= included.height.saturating_add(depth) {
+        return Some(Confirmation::Confirmed);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(n: u8, height: u32) -> Inclusion {
+        Inclusion {
+            block_id: Bytes32::new([n; 32]),
+            height,
+        }
+    }
+
+    #[test]
+    fn waits_until_the_inclusion_is_deep_enough() {
+        let included = block(1, 100);
+        assert_eq!(judge(&included, Some(included), 101, 2), None);
+        assert_eq!(
+            judge(&included, Some(included), 102, 2),
+            Some(Confirmation::Confirmed)
+        );
+    }
+
+    #[test]
+    fn reports_a_replaced_block_as_reorged() {
+        let included = block(1, 100);
+        let reorged = Some(Confirmation::Reorged { included_at: 100 });
+        assert_eq!(judge(&included, Some(block(2, 100)), 101, 2), reorged);
+        assert_eq!(judge(&included, Some(block(2, 103)), 103, 2), reorged);
+        assert_eq!(judge(&included, None, 101, 2), reorged);
+    }
+
+    #[test]
+    fn reports_a_chain_falling_below_the_inclusion_as_reorged() {
+        let included = block(1, 100);
+        assert_eq!(
+            judge(&included, Some(included), 99, 2),
+            Some(Confirmation::Reorged { included_at: 100 })
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use log::info;
+use std::collections::{HashMap, HashSet};
+
+use crate::model::MatchRecord;
+
+/// Defers every match group whose aggregating order was filled against fewer
+/// than `min` distinct counterparty users this cycle, so those matches are
+/// not submitted.
+///
+/// A group is a connected set of matches: orders linked through the matches
+/// they share. Each match fully consumes one of its orders, so a group is a
+/// tree whose leaves are makers filled by a single counterparty. Makers are
+/// never judged on their own. Only the group's aggregating order, the one
+/// with the most distinct counterparty users, has to reach `min`. A group
+/// of one match aggregates a single counterparty. Groups are independent,
+/// so deferring one never thins another. `owners` maps an order id to its
+/// user; an id missing from it counts as its own user.
+pub fn require_counterparties(
+    matches: Vec<MatchRecord>,
+    owners: &HashMap<String, String>,
+    min: usize,
+) -> Vec<MatchRecord> {
+    let mut by_order: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, record) in matches.iter().enumerate() {
+        by_order.entry(record.buy_id.as_str()).or_default().push(i);
+        by_order.entry(record.sell_id.as_str()).or_default().push(i);
+    }
+
+    // Walks each group once, noting the most distinct users any of its
+    // orders was filled against.
+    let mut group_of = vec![usize::MAX; matches.len()];
+    let mut group_breadth: Vec<usize> = Vec::new();
+    for start in 0..matches.len() {
+        if group_of[start] != usize::MAX {
+            continue;
+        }
+        let group = group_breadth.len();
+        let mut breadth = 0;
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut pending = vec![start];
+        group_of[start] = group;
+        while let Some(i) = pending.pop() {
+            for id in [matches[i].buy_id.as_str(), matches[i].sell_id.as_str()] {
+                if !seen.insert(id) {
+                    continue;
+                }
+                let mut users: HashSet<&str> = HashSet::new();
+                for &j in &by_order[id] {
+                    let other = if matches[j].buy_id == id {
+                        &matches[j].sell_id
+                    } else {
+                        &matches[j].buy_id
+                    };
+                    users.insert(owner(owners, other));
+                    if group_of[j] == usize::MAX {
+                        group_of[j] = group;
+                        pending.push(j);
+                    }
+                }
+                breadth = breadth.max(users.len());
+            }
+        }
+        group_breadth.push(breadth);
+    }
+
+    let thin = group_breadth.iter().filter(|&&b| b < min).count();
+    if thin > 0 {
+        info!(
+            "Deferring {} match group(s) aggregating fewer than {} counterparties",
+            thin, min
+        );
+    }
+    matches
+        .into_iter()
+        .zip(group_of)
+        .filter(|(_, group)| group_breadth[*group] >= min)
+        .map(|(record, _)| record)
+        .collect()
+}
+
+fn owner<'a>(owners: &'a HashMap<String, String>, id: &'a str) -> &'a str {
+    owners.get(id).map(String::as_str).unwrap_or(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(buy_id: &str, sell_id: &str) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount: 1,
+            price: 1,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn owners(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(id, user)| (id.to_string(), user.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn defers_a_sweep_against_a_single_counterparty() {
+        let matches = vec![record("b1", "s1"), record("b1", "s2")];
+        let owners = owners(&[("b1", "alice"), ("s1", "bob"), ("s2", "bob")]);
+        assert!(require_counterparties(matches, &owners, 2).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_makers_of_a_broad_sweep() {
+        let matches = vec![record("b1", "s1"), record("b1", "s2"), record("b2", "s3")];
+        let owners = owners(&[
+            ("b1", "alice"),
+            ("s1", "bob"),
+            ("s2", "carol"),
+            ("b2", "alice"),
+            ("s3", "dave"),
+        ]);
+        let kept = require_counterparties(matches, &owners, 2);
+        assert_eq!(kept, vec![record("b1", "s1"), record("b1", "s2")]);
+    }
+}
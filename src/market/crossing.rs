@@ -0,0 +1,1254 @@
+use log::info;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::model::{MatchRecord, SpotOrder};
+
+/// Which side of a match is credited with the price improvement, i.e. the
+/// gap between the buy's limit and the sell's limit times the amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImprovementPolicy {
+    /// The buyer pays the ask and keeps the whole gap.
+    #[default]
+    Buyer,
+    /// The seller receives the bid and keeps the whole gap.
+    Seller,
+    /// Half each; an odd unit goes to the buyer.
+    Split,
+}
+
+impl FromStr for ImprovementPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Buyer" => Ok(ImprovementPolicy::Buyer),
+            "Seller" => Ok(ImprovementPolicy::Seller),
+            "Split" => Ok(ImprovementPolicy::Split),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown improvement policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl ImprovementPolicy {
+    /// Splits a match's price improvement into `(buyer, seller)` shares.
+    /// Saturates instead of overflowing on absurd prices.
+    pub fn attribute(&self, buy_price: u128, sell_price: u128, amount: u128) -> (u128, u128) {
+        let improvement = buy_price.saturating_sub(sell_price).saturating_mul(amount);
+        match self {
+            ImprovementPolicy::Buyer => (improvement, 0),
+            ImprovementPolicy::Seller => (0, improvement),
+            ImprovementPolicy::Split => {
+                let seller = improvement / 2;
+                (improvement - seller, seller)
+            }
+        }
+    }
+}
+
+/// Venue fee rates in basis points of a match's `amount * price`. Every
+/// match has one maker earning the rebate and one taker paying the fee, so
+/// the totals do not depend on which side is which.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FeeSchedule {
+    pub maker_rebate_bps: u128,
+    pub taker_fee_bps: u128,
+}
+
+impl FeeSchedule {
+    /// `(maker_rebate, taker_fee)` of one match. Saturates instead of
+    /// overflowing on absurd prices.
+    pub fn apply(&self, price: u128, amount: u128) -> (u128, u128) {
+        let notional = amount.saturating_mul(price);
+        (
+            notional.saturating_mul(self.maker_rebate_bps) / 10_000,
+            notional.saturating_mul(self.taker_fee_bps) / 10_000,
+        )
+    }
+}
+
+/// How the crossing loop pairs buys with sells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchStrategy {
+    /// Repeatedly cross the best buy with the best sell.
+    #[default]
+    Greedy,
+    /// Maximise the total matched amount. Greedy can strand volume: with
+    /// buys at 10 and 5 and sells at 4 and 9, it pairs 10 with 4 and then
+    /// cannot match 5 with 9, where pairing 10/9 and 5/4 matches both.
+    MaxVolume,
+}
+
+impl FromStr for MatchStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Greedy" => Ok(MatchStrategy::Greedy),
+            "MaxVolume" => Ok(MatchStrategy::MaxVolume),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown match strategy {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Which side wins a tie when a buy and a sell of exactly equal size meet
+/// one of the crossing loop's either/or decisions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidePriority {
+    Buy,
+    Sell,
+}
+
+impl FromStr for SidePriority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Buy" => Ok(SidePriority::Buy),
+            "Sell" => Ok(SidePriority::Sell),
+            _ => Err(Error::StringParsingError(format!("unknown side {}", s))),
+        }
+    }
+}
+
+/// What to do when the best buy and best sell belong to the same user.
+///
+/// "Cancel" only takes the order out of this cycle's crossing; nothing is
+/// cancelled on chain, and the order is back in the book with the next feed
+/// update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StpPolicy {
+    /// Leave both resting: the buy sits out the rest of the cycle and the
+    /// sell stays available to other buyers.
+    Skip,
+    /// Drop whichever of the two has the later timestamp.
+    CancelNewest,
+    /// Drop whichever of the two has the earlier timestamp.
+    CancelOldest,
+    /// Reduce both by the overlapping amount without submitting a match.
+    DecrementBoth,
+}
+
+impl FromStr for StpPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Skip" => Ok(StpPolicy::Skip),
+            "CancelNewest" => Ok(StpPolicy::CancelNewest),
+            "CancelOldest" => Ok(StpPolicy::CancelOldest),
+            "DecrementBoth" => Ok(StpPolicy::DecrementBoth),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown STP policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Per-cycle limits applied while crossing the book.
+#[derive(Debug, Clone, Default)]
+pub struct MatchSettings {
+    /// Ceiling on the cumulative `amount * sell_price` matched in one cycle,
+    /// in raw contract units. Crossing stops before the match that would
+    /// exceed it.
+    pub max_cycle_notional: Option<u128>,
+    /// Floor on a single match's `amount * sell_price`. A match below it is
+    /// not made and the smaller of its two orders sits out the cycle, so
+    /// low-priced orders cannot slip dust past an amount-only check.
+    pub min_match_notional: Option<u128>,
+    /// Ceiling on the amount filled per user in one cycle, counted over both
+    /// sides. Once a user reaches it their remaining orders sit out the cycle.
+    pub max_fill_per_owner: Option<u128>,
+    /// Take orders at one price oldest first instead of in heap order, so a
+    /// sweep reaches makers in FIFO order and the matches of a sweep come out
+    /// in that order for chunking.
+    pub time_priority: bool,
+    /// Tags taken first among orders at one price, most preferred first.
+    /// Untagged and unlisted orders come after every listed tag; ties are
+    /// then broken by `time_priority`. Not applied by `MaxVolume`.
+    pub tag_priority: Vec<String>,
+    /// Require the buy price to be strictly above the sell price; by default
+    /// orders at exactly the same price match.
+    pub strict_crossing: bool,
+    /// Only match at the best this-many settlement (sell) price levels per
+    /// cycle; deeper crosses wait for the following cycles.
+    pub max_price_levels: Option<usize>,
+    /// Spread a larger order's fill evenly over every resting order at the
+    /// level it crosses instead of consuming them one at a time. Orders at one
+    /// price come off the heap in no particular order, so this trades the
+    /// arbitrary "first maker takes all" outcome for an even split.
+    pub fair_allocation: bool,
+    /// Self-trade prevention; `None` lets same-user orders cross.
+    pub self_trade_prevention: Option<StpPolicy>,
+    pub improvement_policy: ImprovementPolicy,
+    /// Rebates and fees to total up per cycle; `None` skips the accounting.
+    pub fees: Option<FeeSchedule>,
+    /// Tie-break for equal sizes. With `fair_allocation` the favoured side
+    /// sweeps the other side's level, and under `min_match_notional` it stays
+    /// in the cycle while the other side is deferred. `None` keeps the
+    /// defaults: the buy sweeps, and the buy is deferred.
+    pub equal_size_priority: Option<SidePriority>,
+    /// `MaxVolume` only runs when none of the per-match or per-cycle limits
+    /// above are set; otherwise the cycle falls back to `Greedy`.
+    pub strategy: MatchStrategy,
+}
+
+impl MatchSettings {
+    fn has_limits(&self) -> bool {
+        self.max_cycle_notional.is_some()
+            || self.min_match_notional.is_some()
+            || self.max_fill_per_owner.is_some()
+            || self.max_price_levels.is_some()
+            || self.self_trade_prevention.is_some()
+            || self.fair_allocation
+    }
+
+    /// Whether orders at one price are ranked at all, rather than taken in
+    /// heap order.
+    fn ranks_levels(&self) -> bool {
+        self.time_priority || !self.tag_priority.is_empty()
+    }
+
+    /// Sort key within a price level, lowest taken first.
+    fn level_rank(&self, order: &SpotOrder) -> (usize, u64) {
+        let tag_rank = order
+            .tag
+            .as_ref()
+            .and_then(|tag| self.tag_priority.iter().position(|t| t == tag))
+            .unwrap_or(self.tag_priority.len());
+        let time_rank = if self.time_priority {
+            order.timestamp
+        } else {
+            0
+        };
+        (tag_rank, time_rank)
+    }
+}
+
+/// How often each kind of skip happened in one crossing, to explain why
+/// crossing orders were left unfilled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SkipCounts {
+    pub zero_amount: u32,
+    pub self_trade: u32,
+    /// Matches below `min_match_notional`, i.e. dust.
+    pub below_notional_floor: u32,
+    pub owner_capped: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct CrossingOutcome {
+    /// In the order they were crossed.
+    pub matches: Vec<MatchRecord>,
+    pub total_amount: u128,
+    pub remaining_buys: usize,
+    pub remaining_sells: usize,
+    /// Amount left on the orders counted in `remaining_buys`/`remaining_sells`.
+    pub unmatched_buy_volume: u128,
+    pub unmatched_sell_volume: u128,
+    pub skips: SkipCounts,
+    /// Orders taken out of the cycle by `CancelNewest`/`CancelOldest`.
+    pub self_trade_cancelled: Vec<String>,
+    /// Price improvement credited to buyers and sellers this cycle.
+    pub buyer_improvement: u128,
+    pub seller_improvement: u128,
+    /// Totals under `MatchSettings::fees`, zero without a schedule.
+    pub maker_rebates: u128,
+    pub taker_fees: u128,
+}
+
+/// Why the crossing loop made or skipped a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+pub enum StepOutcome {
+    Matched,
+    /// The best buy does not cross the best sell; the buy is done for the
+    /// cycle.
+    NotCrossing,
+    /// One of the pair has nothing left to fill and was dropped.
+    ZeroAmount,
+    SelfTrade,
+    BelowNotionalFloor,
+    OwnerCapped,
+    /// The cycle notional ceiling was hit and crossing stopped.
+    NotionalCeiling,
+    /// The price level cap was hit and crossing stopped.
+    LevelLimit,
+}
+
+/// One decision of the crossing loop. `amount` is the fill that was made or
+/// considered, zero when no fill was attempted.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct CrossingStep {
+    pub buy_id: String,
+    pub sell_id: String,
+    pub buy_price: u128,
+    pub sell_price: u128,
+    pub amount: u128,
+    pub outcome: StepOutcome,
+}
+
+/// Counts skips, and collects `CrossingStep`s when enabled.
+#[derive(Default)]
+struct Trace {
+    steps: Option<Vec<CrossingStep>>,
+    skips: SkipCounts,
+}
+
+impl Trace {
+    fn push(&mut self, buy: &SpotOrder, sell: &SpotOrder, amount: u128, outcome: StepOutcome) {
+        match outcome {
+            StepOutcome::ZeroAmount => self.skips.zero_amount += 1,
+            StepOutcome::SelfTrade => self.skips.self_trade += 1,
+            StepOutcome::BelowNotionalFloor => self.skips.below_notional_floor += 1,
+            StepOutcome::OwnerCapped => self.skips.owner_capped += 1,
+            _ => {}
+        }
+        if let Some(steps) = &mut self.steps {
+            steps.push(CrossingStep {
+                buy_id: buy.id.clone(),
+                sell_id: sell.id.clone(),
+                buy_price: buy.price,
+                sell_price: sell.price,
+                amount,
+                outcome,
+            });
+        }
+    }
+}
+
+pub fn compute_matches(
+    buy_queue: BinaryHeap<SpotOrder>,
+    sell_queue: BinaryHeap<Reverse<SpotOrder>>,
+    settings: &MatchSettings,
+) -> CrossingOutcome {
+    cross(buy_queue, sell_queue, settings, &mut Trace::default())
+}
+
+/// Runs the same crossing as `compute_matches` and also returns every
+/// decision it made, for diagnosing why orders did or did not match.
+pub fn explain_matches(
+    buy_queue: BinaryHeap<SpotOrder>,
+    sell_queue: BinaryHeap<Reverse<SpotOrder>>,
+    settings: &MatchSettings,
+) -> (CrossingOutcome, Vec<CrossingStep>) {
+    let mut trace = Trace {
+        steps: Some(Vec::new()),
+        ..Default::default()
+    };
+    let outcome = cross(buy_queue, sell_queue, settings, &mut trace);
+    (outcome, trace.steps.unwrap_or_default())
+}
+
+fn cross(
+    mut buy_queue: BinaryHeap<SpotOrder>,
+    mut sell_queue: BinaryHeap<Reverse<SpotOrder>>,
+    settings: &MatchSettings,
+    trace: &mut Trace,
+) -> CrossingOutcome {
+    if buy_queue.is_empty() || sell_queue.is_empty() {
+        return CrossingOutcome {
+            remaining_buys: buy_queue.len(),
+            remaining_sells: sell_queue.len(),
+            unmatched_buy_volume: buy_queue
+                .iter()
+                .map(|order| order.amount)
+                .fold(0, u128::saturating_add),
+            unmatched_sell_volume: sell_queue
+                .iter()
+                .map(|Reverse(order)| order.amount)
+                .fold(0, u128::saturating_add),
+            ..Default::default()
+        };
+    }
+
+    if settings.strategy == MatchStrategy::MaxVolume {
+        if !settings.has_limits() {
+            return max_volume(buy_queue, sell_queue, settings, trace);
+        }
+        info!("MaxVolume does not apply cycle or match limits, crossing greedily");
+    }
+
+    let mut matches: Vec<MatchRecord> = Vec::new();
+    let mut total_amount: u128 = 0;
+    let mut total_notional: u128 = 0;
+    let mut deferred_buys: Vec<SpotOrder> = Vec::new();
+    let mut deferred_sells: Vec<SpotOrder> = Vec::new();
+    let mut self_trade_cancelled: Vec<String> = Vec::new();
+    let mut buyer_improvement: u128 = 0;
+    let mut seller_improvement: u128 = 0;
+    let mut maker_rebates: u128 = 0;
+    let mut taker_fees: u128 = 0;
+    let mut owner_fills: HashMap<String, u128> = HashMap::new();
+    let mut price_levels: HashSet<u128> = HashSet::new();
+    // Buys that cannot cross the best sell leave the queue for good.
+    let mut uncrossed_buy_volume: u128 = 0;
+
+    while let (Some(buy_order), Some(Reverse(sell_order))) = (
+        pop_best(&mut buy_queue, settings),
+        pop_best(&mut sell_queue, settings),
+    ) {
+        // Rejected at ingest, but a zero-amount order must never become a
+        // zero-amount match if one slips through.
+        if buy_order.amount == 0 || sell_order.amount == 0 {
+            trace.push(&buy_order, &sell_order, 0, StepOutcome::ZeroAmount);
+            if buy_order.amount > 0 {
+                buy_queue.push(buy_order);
+            }
+            if sell_order.amount > 0 {
+                sell_queue.push(Reverse(sell_order));
+            }
+            continue;
+        }
+
+        if !crosses(buy_order.price, sell_order.price, settings.strict_crossing) {
+            trace.push(&buy_order, &sell_order, 0, StepOutcome::NotCrossing);
+            uncrossed_buy_volume = uncrossed_buy_volume.saturating_add(buy_order.amount);
+            sell_queue.push(Reverse(sell_order));
+            continue;
+        }
+
+        if let Some(policy) = settings.self_trade_prevention {
+            if buy_order.user == sell_order.user {
+                trace.push(&buy_order, &sell_order, 0, StepOutcome::SelfTrade);
+                prevent_self_trade(
+                    policy,
+                    buy_order,
+                    sell_order,
+                    &mut buy_queue,
+                    &mut sell_queue,
+                    &mut deferred_buys,
+                    &mut self_trade_cancelled,
+                );
+                continue;
+            }
+        }
+
+        let mut buys = vec![buy_order];
+        let mut sells = vec![sell_order];
+
+        // The larger order sweeps the other side's level. With STP on, orders
+        // of the sweeping order's owner are left out of the level.
+        if settings.fair_allocation {
+            let mut same_owner = Vec::new();
+            let buy_sweeps = match settings.equal_size_priority {
+                Some(SidePriority::Sell) => buys[0].amount > sells[0].amount,
+                _ => buys[0].amount >= sells[0].amount,
+            };
+            if buy_sweeps {
+                while let Some(Reverse(next)) = sell_queue.peek() {
+                    if next.price != sells[0].price {
+                        break;
+                    }
+                    if let Some(Reverse(order)) = sell_queue.pop() {
+                        if settings.self_trade_prevention.is_some() && order.user == buys[0].user {
+                            same_owner.push(order);
+                        } else {
+                            sells.push(order);
+                        }
+                    }
+                }
+                sell_queue.extend(same_owner.into_iter().map(Reverse));
+            } else {
+                while let Some(next) = buy_queue.peek() {
+                    if next.price != buys[0].price {
+                        break;
+                    }
+                    if let Some(order) = buy_queue.pop() {
+                        if settings.self_trade_prevention.is_some() && order.user == sells[0].user {
+                            same_owner.push(order);
+                        } else {
+                            buys.push(order);
+                        }
+                    }
+                }
+                buy_queue.extend(same_owner);
+            }
+
+            if settings.ranks_levels() {
+                buys.sort_by_key(|order| settings.level_rank(order));
+                sells.sort_by_key(|order| settings.level_rank(order));
+            }
+        }
+
+        let fills: Vec<(usize, usize, u128)> = if buys.len() > 1 {
+            split_evenly(sells[0].amount, &buys)
+                .into_iter()
+                .enumerate()
+                .map(|(i, amount)| (i, 0, amount))
+                .collect()
+        } else if sells.len() > 1 {
+            split_evenly(buys[0].amount, &sells)
+                .into_iter()
+                .enumerate()
+                .map(|(j, amount)| (0, j, amount))
+                .collect()
+        } else {
+            vec![(0, 0, std::cmp::min(buys[0].amount, sells[0].amount))]
+        };
+
+        let mut ceiling_reached = false;
+        let mut defer_buy = vec![false; buys.len()];
+        let mut defer_sell = vec![false; sells.len()];
+        for (i, j, mut match_amount) in fills {
+            if match_amount == 0 {
+                continue;
+            }
+
+            if let Some(cap) = settings.max_fill_per_owner {
+                let room =
+                    |user: &str| cap.saturating_sub(owner_fills.get(user).copied().unwrap_or(0));
+                let buyer_room = room(&buys[i].user);
+                let seller_room = room(&sells[j].user);
+                if buyer_room == 0 || seller_room == 0 {
+                    info!(
+                        "Owner fill cap {} reached on {} x {}, deferring the capped side",
+                        cap, buys[i].id, sells[j].id
+                    );
+                    trace.push(&buys[i], &sells[j], match_amount, StepOutcome::OwnerCapped);
+                    defer_buy[i] |= buyer_room == 0;
+                    defer_sell[j] |= seller_room == 0;
+                    continue;
+                }
+                match_amount = match_amount.min(buyer_room).min(seller_room);
+            }
+
+            if let Some(min_notional) = settings.min_match_notional {
+                // An overflowing product is above any floor.
+                if match_amount.saturating_mul(sells[j].price) < min_notional {
+                    info!(
+                        "Match {} x {} of {} below notional floor {}, deferring the smaller order",
+                        buys[i].id, sells[j].id, match_amount, min_notional
+                    );
+                    trace.push(
+                        &buys[i],
+                        &sells[j],
+                        match_amount,
+                        StepOutcome::BelowNotionalFloor,
+                    );
+                    let defer_buy_side = match settings.equal_size_priority {
+                        Some(SidePriority::Buy) => buys[i].amount < sells[j].amount,
+                        _ => buys[i].amount <= sells[j].amount,
+                    };
+                    if defer_buy_side {
+                        defer_buy[i] = true;
+                    } else {
+                        defer_sell[j] = true;
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(max_levels) = settings.max_price_levels {
+                if !price_levels.contains(&sells[j].price) && price_levels.len() >= max_levels {
+                    info!(
+                        "Matched {} price level(s), deferring deeper crosses",
+                        max_levels
+                    );
+                    trace.push(&buys[i], &sells[j], match_amount, StepOutcome::LevelLimit);
+                    ceiling_reached = true;
+                    break;
+                }
+            }
+
+            if let Some(max_notional) = settings.max_cycle_notional {
+                // Valued at the sell price the match settles at. An
+                // overflowing product is treated as exceeding any ceiling.
+                let notional = match_amount
+                    .checked_mul(sells[j].price)
+                    .and_then(|n| n.checked_add(total_notional));
+                match notional {
+                    Some(n) if n <= max_notional => total_notional = n,
+                    _ => {
+                        info!(
+                            "Cycle notional ceiling {} reached, deferring remaining orders",
+                            max_notional
+                        );
+                        trace.push(
+                            &buys[i],
+                            &sells[j],
+                            match_amount,
+                            StepOutcome::NotionalCeiling,
+                        );
+                        ceiling_reached = true;
+                        break;
+                    }
+                }
+            }
+
+            trace.push(&buys[i], &sells[j], match_amount, StepOutcome::Matched);
+            matches.push(MatchRecord {
+                buy_id: buys[i].id.clone(),
+                sell_id: sells[j].id.clone(),
+                amount: match_amount,
+                price: sells[j].price,
+                buy_tag: buys[i].tag.clone(),
+                sell_tag: sells[j].tag.clone(),
+            });
+            price_levels.insert(sells[j].price);
+            total_amount = total_amount.saturating_add(match_amount);
+            if settings.max_fill_per_owner.is_some() {
+                for user in [&buys[i].user, &sells[j].user] {
+                    let filled = owner_fills.entry(user.clone()).or_default();
+                    *filled = filled.saturating_add(match_amount);
+                }
+            }
+
+            let (to_buyer, to_seller) =
+                settings
+                    .improvement_policy
+                    .attribute(buys[i].price, sells[j].price, match_amount);
+            if to_buyer + to_seller > 0 {
+                info!(
+                    "Price improvement on {} x {}: {} to buyer, {} to seller",
+                    buys[i].id, sells[j].id, to_buyer, to_seller
+                );
+            }
+            buyer_improvement = buyer_improvement.saturating_add(to_buyer);
+            seller_improvement = seller_improvement.saturating_add(to_seller);
+
+            if let Some(fees) = &settings.fees {
+                let (rebate, fee) = fees.apply(sells[j].price, match_amount);
+                maker_rebates = maker_rebates.saturating_add(rebate);
+                taker_fees = taker_fees.saturating_add(fee);
+            }
+
+            buys[i].amount -= match_amount;
+            sells[j].amount -= match_amount;
+        }
+
+        for (i, buy_order) in buys.into_iter().enumerate() {
+            if buy_order.amount == 0 {
+                continue;
+            }
+            if defer_buy[i] {
+                deferred_buys.push(buy_order);
+            } else {
+                buy_queue.push(buy_order);
+            }
+        }
+
+        for (j, sell_order) in sells.into_iter().enumerate() {
+            if sell_order.amount == 0 {
+                continue;
+            }
+            if defer_sell[j] {
+                deferred_sells.push(sell_order);
+            } else {
+                sell_queue.push(Reverse(sell_order));
+            }
+        }
+
+        if ceiling_reached {
+            break;
+        }
+    }
+
+    buy_queue.extend(deferred_buys);
+    sell_queue.extend(deferred_sells.into_iter().map(Reverse));
+
+    CrossingOutcome {
+        matches,
+        total_amount,
+        remaining_buys: buy_queue.len(),
+        remaining_sells: sell_queue.len(),
+        unmatched_buy_volume: buy_queue
+            .iter()
+            .map(|order| order.amount)
+            .fold(uncrossed_buy_volume, u128::saturating_add),
+        unmatched_sell_volume: sell_queue
+            .iter()
+            .map(|Reverse(order)| order.amount)
+            .fold(0, u128::saturating_add),
+        skips: trace.skips,
+        self_trade_cancelled,
+        buyer_improvement,
+        seller_improvement,
+        maker_rebates,
+        taker_fees,
+    }
+}
+
+/// Every buy can fill against all sells at or below its price, so the
+/// sets of compatible sells are nested. Serving buys from the lowest price
+/// up, each taking the cheapest sells still open, leaves every more
+/// expensive sell to the higher buys that can afford it and matches the
+/// most volume possible. Runs in O(n log n), so no book size is too large.
+fn max_volume(
+    buy_queue: BinaryHeap<SpotOrder>,
+    sell_queue: BinaryHeap<Reverse<SpotOrder>>,
+    settings: &MatchSettings,
+    trace: &mut Trace,
+) -> CrossingOutcome {
+    let mut buys: Vec<SpotOrder> = buy_queue
+        .into_vec()
+        .into_iter()
+        .filter(|order| order.amount > 0)
+        .collect();
+    buys.sort_by_key(|order| order.price);
+    let mut sells: Vec<SpotOrder> = sell_queue
+        .into_vec()
+        .into_iter()
+        .map(|Reverse(order)| order)
+        .filter(|order| order.amount > 0)
+        .collect();
+    sells.sort_by_key(|order| order.price);
+
+    let mut outcome = CrossingOutcome::default();
+    let mut next_sell = 0;
+    for buy in buys.iter_mut() {
+        while buy.amount > 0
+            && next_sell < sells.len()
+            && crosses(buy.price, sells[next_sell].price, settings.strict_crossing)
+        {
+            let sell = &mut sells[next_sell];
+            let match_amount = std::cmp::min(buy.amount, sell.amount);
+            trace.push(buy, sell, match_amount, StepOutcome::Matched);
+            outcome.matches.push(MatchRecord {
+                buy_id: buy.id.clone(),
+                sell_id: sell.id.clone(),
+                amount: match_amount,
+                price: sell.price,
+                buy_tag: buy.tag.clone(),
+                sell_tag: sell.tag.clone(),
+            });
+            outcome.total_amount = outcome.total_amount.saturating_add(match_amount);
+
+            let (to_buyer, to_seller) =
+                settings
+                    .improvement_policy
+                    .attribute(buy.price, sell.price, match_amount);
+            outcome.buyer_improvement = outcome.buyer_improvement.saturating_add(to_buyer);
+            outcome.seller_improvement = outcome.seller_improvement.saturating_add(to_seller);
+
+            if let Some(fees) = &settings.fees {
+                let (rebate, fee) = fees.apply(sell.price, match_amount);
+                outcome.maker_rebates = outcome.maker_rebates.saturating_add(rebate);
+                outcome.taker_fees = outcome.taker_fees.saturating_add(fee);
+            }
+
+            buy.amount -= match_amount;
+            sell.amount -= match_amount;
+            if sell.amount == 0 {
+                next_sell += 1;
+            }
+        }
+    }
+
+    outcome.remaining_buys = buys.iter().filter(|order| order.amount > 0).count();
+    outcome.remaining_sells = sells.len() - next_sell;
+    outcome.unmatched_buy_volume = buys
+        .iter()
+        .map(|order| order.amount)
+        .fold(0, u128::saturating_add);
+    outcome.unmatched_sell_volume = sells[next_sell..]
+        .iter()
+        .map(|order| order.amount)
+        .fold(0, u128::saturating_add);
+    outcome
+}
+
+fn prevent_self_trade(
+    policy: StpPolicy,
+    mut buy_order: SpotOrder,
+    mut sell_order: SpotOrder,
+    buy_queue: &mut BinaryHeap<SpotOrder>,
+    sell_queue: &mut BinaryHeap<Reverse<SpotOrder>>,
+    deferred_buys: &mut Vec<SpotOrder>,
+    cancelled: &mut Vec<String>,
+) {
+    info!(
+        "Self-trade between buy {} and sell {} of {}, applying {:?}",
+        buy_order.id, sell_order.id, buy_order.user, policy
+    );
+
+    let buy_is_newer = buy_order.timestamp >= sell_order.timestamp;
+    match policy {
+        StpPolicy::Skip => {
+            deferred_buys.push(buy_order);
+            sell_queue.push(Reverse(sell_order));
+        }
+        StpPolicy::CancelNewest | StpPolicy::CancelOldest => {
+            let drop_buy = buy_is_newer == (policy == StpPolicy::CancelNewest);
+            if drop_buy {
+                cancelled.push(buy_order.id);
+                sell_queue.push(Reverse(sell_order));
+            } else {
+                cancelled.push(sell_order.id);
+                buy_queue.push(buy_order);
+            }
+        }
+        StpPolicy::DecrementBoth => {
+            let overlap = std::cmp::min(buy_order.amount, sell_order.amount);
+            buy_order.amount -= overlap;
+            sell_order.amount -= overlap;
+            if buy_order.amount > 0 {
+                buy_queue.push(buy_order);
+            }
+            if sell_order.amount > 0 {
+                sell_queue.push(Reverse(sell_order));
+            }
+        }
+    }
+}
+
+fn crosses(buy_price: u128, sell_price: u128, strict: bool) -> bool {
+    if strict {
+        buy_price > sell_price
+    } else {
+        buy_price >= sell_price
+    }
+}
+
+/// Heap entries of either side of the book.
+trait Queued: Ord {
+    fn order(&self) -> &SpotOrder;
+}
+
+impl Queued for SpotOrder {
+    fn order(&self) -> &SpotOrder {
+        self
+    }
+}
+
+impl Queued for Reverse<SpotOrder> {
+    fn order(&self) -> &SpotOrder {
+        &self.0
+    }
+}
+
+/// Pops the best-priced entry; with `tag_priority` or `time_priority`, the
+/// best-ranked one at that price, putting the rest of the level back.
+fn pop_best<T: Queued>(queue: &mut BinaryHeap<T>, settings: &MatchSettings) -> Option<T> {
+    let mut best = queue.pop()?;
+    if !settings.ranks_levels() {
+        return Some(best);
+    }
+
+    let mut level = Vec::new();
+    while let Some(next) = queue.peek() {
+        if next.order().price != best.order().price {
+            break;
+        }
+        if let Some(next) = queue.pop() {
+            if settings.level_rank(next.order()) < settings.level_rank(best.order()) {
+                level.push(std::mem::replace(&mut best, next));
+            } else {
+                level.push(next);
+            }
+        }
+    }
+    queue.extend(level);
+    Some(best)
+}
+
+/// Water-fills `amount` over `makers`: every maker gets an equal share, capped
+/// at its own size, with what the small ones cannot take passed on to the
+/// rest. Returns the fill per maker in the input order.
+fn split_evenly(amount: u128, makers: &[SpotOrder]) -> Vec<u128> {
+    let mut fills = vec![0; makers.len()];
+    let mut by_size: Vec<usize> = (0..makers.len()).collect();
+    by_size.sort_by_key(|&i| makers[i].amount);
+
+    let mut remaining = amount;
+    for (n, &i) in by_size.iter().enumerate() {
+        let share = remaining / (makers.len() - n) as u128;
+        let fill = std::cmp::min(makers[i].amount, share);
+        fills[i] = fill;
+        remaining -= fill;
+    }
+    fills
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::OrderType;
+
+    /// Crosses the book in `fixtures/<book>.book` and compares the outcome
+    /// with `fixtures/<golden>.golden`, `<book>.golden` when not given: one
+    /// `buy_id sell_id amount price` line per match in crossing order, then
+    /// one `cancelled id` line per order taken out by self-trade prevention.
+    macro_rules! assert_golden {
+        ($name:literal, $settings:expr) => {
+            assert_golden!($name, $name, $settings)
+        };
+        ($book:literal, $golden:literal, $settings:expr) => {
+            let (buys, sells) = book(include_str!(concat!("fixtures/", $book, ".book")));
+            let outcome = compute_matches(buys, sells, &$settings);
+            let lines: Vec<String> = outcome
+                .matches
+                .iter()
+                .map(|m| format!("{} {} {} {}", m.buy_id, m.sell_id, m.amount, m.price))
+                .chain(
+                    outcome
+                        .self_trade_cancelled
+                        .iter()
+                        .map(|id| format!("cancelled {}", id)),
+                )
+                .collect();
+            let golden: Vec<&str> = include_str!(concat!("fixtures/", $golden, ".golden"))
+                .lines()
+                .collect();
+            assert_eq!(lines, golden, "{} diverged from its golden output", $golden);
+        };
+    }
+
+    /// Parses `side id user amount price timestamp [tag]` lines; blank lines
+    /// and `#` comments are skipped.
+    fn book(fixture: &str) -> (BinaryHeap<SpotOrder>, BinaryHeap<Reverse<SpotOrder>>) {
+        let mut buys = BinaryHeap::new();
+        let mut sells = BinaryHeap::new();
+        for line in fixture.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let order_type = match fields[0] {
+                "buy" => OrderType::Buy,
+                "sell" => OrderType::Sell,
+                side => panic!("unknown side {} in fixture", side),
+            };
+            let order = SpotOrder {
+                id: fields[1].to_string(),
+                user: fields[2].to_string(),
+                asset: String::new(),
+                amount: fields[3].parse().unwrap(),
+                price: fields[4].parse().unwrap(),
+                timestamp: fields[5].parse().unwrap(),
+                order_type,
+                tag: fields.get(6).map(|tag| tag.to_string()),
+                received_at: 0,
+            };
+            match order_type {
+                OrderType::Buy => buys.push(order),
+                OrderType::Sell => sells.push(Reverse(order)),
+            }
+        }
+        (buys, sells)
+    }
+
+    #[test]
+    fn sweep() {
+        assert_golden!("sweep", MatchSettings::default());
+    }
+
+    #[test]
+    fn dust() {
+        assert_golden!(
+            "dust",
+            MatchSettings {
+                min_match_notional: Some(100),
+                ..Default::default()
+            }
+        );
+    }
+
+    fn stp(policy: StpPolicy) -> MatchSettings {
+        MatchSettings {
+            self_trade_prevention: Some(policy),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn self_trade_skip() {
+        assert_golden!("self_trade", "self_trade_skip", stp(StpPolicy::Skip));
+    }
+
+    #[test]
+    fn self_trade_cancel_newest() {
+        assert_golden!(
+            "self_trade",
+            "self_trade_cancel_newest",
+            stp(StpPolicy::CancelNewest)
+        );
+    }
+
+    #[test]
+    fn self_trade_cancel_oldest() {
+        assert_golden!(
+            "self_trade",
+            "self_trade_cancel_oldest",
+            stp(StpPolicy::CancelOldest)
+        );
+    }
+
+    #[test]
+    fn self_trade_decrement_both() {
+        assert_golden!(
+            "self_trade",
+            "self_trade_decrement_both",
+            stp(StpPolicy::DecrementBoth)
+        );
+    }
+
+    #[test]
+    fn time_priority() {
+        assert_golden!(
+            "time_priority",
+            MatchSettings {
+                time_priority: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn tag_priority() {
+        assert_golden!(
+            "tag_priority",
+            MatchSettings {
+                tag_priority: vec!["mm".to_string(), "retail".to_string()],
+                time_priority: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn fair_allocation() {
+        assert_golden!(
+            "fair_allocation",
+            MatchSettings {
+                fair_allocation: true,
+                time_priority: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    fn equal_size(priority: Option<SidePriority>) -> MatchSettings {
+        MatchSettings {
+            fair_allocation: true,
+            time_priority: true,
+            equal_size_priority: priority,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn equal_size_buy_priority() {
+        assert_golden!("equal_size", "equal_size_buy", equal_size(None));
+        assert_golden!(
+            "equal_size",
+            "equal_size_buy",
+            equal_size(Some(SidePriority::Buy))
+        );
+    }
+
+    #[test]
+    fn equal_size_sell_priority() {
+        assert_golden!(
+            "equal_size",
+            "equal_size_sell",
+            equal_size(Some(SidePriority::Sell))
+        );
+    }
+
+    #[test]
+    fn max_volume() {
+        assert_golden!(
+            "max_volume",
+            MatchSettings {
+                strategy: MatchStrategy::MaxVolume,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn max_volume_beats_greedy_on_a_lumpy_book() {
+        assert_golden!("max_volume", "max_volume_greedy", MatchSettings::default());
+    }
+
+    #[test]
+    fn max_volume_falls_back_to_greedy_under_limits() {
+        assert_golden!(
+            "max_volume",
+            "max_volume_greedy",
+            MatchSettings {
+                strategy: MatchStrategy::MaxVolume,
+                max_price_levels: Some(10),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn price_levels() {
+        assert_golden!(
+            "price_levels",
+            MatchSettings {
+                max_price_levels: Some(2),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn notional_ceiling() {
+        assert_golden!(
+            "notional_ceiling",
+            MatchSettings {
+                max_cycle_notional: Some(500),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn improvement_goes_to_the_side_the_policy_names() {
+        // Buy at 10, sell at 7: 3 of improvement on each of 5 units.
+        assert_eq!(ImprovementPolicy::Buyer.attribute(10, 7, 5), (15, 0));
+        assert_eq!(ImprovementPolicy::Seller.attribute(10, 7, 5), (0, 15));
+        // The odd unit of a split goes to the buyer.
+        assert_eq!(ImprovementPolicy::Split.attribute(10, 7, 5), (8, 7));
+        assert_eq!(ImprovementPolicy::Split.attribute(10, 10, 5), (0, 0));
+        assert_eq!(
+            ImprovementPolicy::Buyer.attribute(u128::MAX, 1, 2),
+            (u128::MAX, 0)
+        );
+
+        // The sweep fills b1 (limit 10) at 7, 9 and 10.
+        for (policy, expected) in [
+            (ImprovementPolicy::Buyer, (190, 0)),
+            (ImprovementPolicy::Seller, (0, 190)),
+            (ImprovementPolicy::Split, (95, 95)),
+        ] {
+            let (buys, sells) = book(include_str!("fixtures/sweep.book"));
+            let settings = MatchSettings {
+                improvement_policy: policy,
+                ..Default::default()
+            };
+            let outcome = compute_matches(buys, sells, &settings);
+            assert_eq!(
+                (outcome.buyer_improvement, outcome.seller_improvement),
+                expected,
+                "{:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn zero_amount_orders_are_skipped_not_matched() {
+        let fixture = "buy b1 u1 0 10 1\nbuy b2 u2 4 10 2\nsell s1 u3 0 9 1\nsell s2 u4 4 9 2";
+        let (buys, sells) = book(fixture);
+        let outcome = compute_matches(buys, sells, &MatchSettings::default());
+        let matches: Vec<(&str, &str, u128)> = outcome
+            .matches
+            .iter()
+            .map(|m| (m.buy_id.as_str(), m.sell_id.as_str(), m.amount))
+            .collect();
+        assert_eq!(matches, [("b2", "s2", 4)]);
+        assert!(outcome.skips.zero_amount > 0);
+    }
+
+    #[test]
+    fn equal_prices_match_unless_crossing_is_strict() {
+        let equal = "buy b1 u1 5 10 1\nsell s1 u2 5 10 1";
+        let crossed = "buy b1 u1 5 11 1\nsell s1 u2 5 10 1";
+        for strategy in [MatchStrategy::Greedy, MatchStrategy::MaxVolume] {
+            for (fixture, strict_crossing, matched) in [
+                (equal, false, 5),
+                (equal, true, 0),
+                (crossed, false, 5),
+                (crossed, true, 5),
+            ] {
+                let (buys, sells) = book(fixture);
+                let settings = MatchSettings {
+                    strict_crossing,
+                    strategy,
+                    ..Default::default()
+                };
+                let outcome = compute_matches(buys, sells, &settings);
+                assert_eq!(
+                    (outcome.total_amount, outcome.unmatched_buy_volume),
+                    (matched, 5 - matched),
+                    "{:?} strict {} on {:?}",
+                    strategy,
+                    strict_crossing,
+                    fixture
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fees_and_rebates_at_known_rates() {
+        let fees = FeeSchedule {
+            maker_rebate_bps: 100,
+            taker_fee_bps: 250,
+        };
+        // 40 at 9 is 360 of notional: 1% back to the maker, 2.5% from the
+        // taker, rounded down.
+        assert_eq!(fees.apply(9, 40), (3, 9));
+        assert_eq!(fees.apply(10, 1_000), (100, 250));
+        assert_eq!(
+            fees.apply(u128::MAX, 2),
+            (u128::MAX / 10_000, u128::MAX / 10_000)
+        );
+
+        // The sweep settles 50 at 7, 40 at 9 and 10 at 10.
+        let (buys, sells) = book(include_str!("fixtures/sweep.book"));
+        let settings = MatchSettings {
+            fees: Some(fees),
+            ..Default::default()
+        };
+        let outcome = compute_matches(buys, sells, &settings);
+        assert_eq!(
+            (outcome.maker_rebates, outcome.taker_fees),
+            (3 + 3 + 1, 8 + 9 + 2)
+        );
+    }
+
+    #[test]
+    fn unmatched_volume_saturates() {
+        let max = u128::MAX;
+        let fixture = format!(
+            "buy b1 u1 {max} 5 1\nbuy b2 u2 {max} 4 1\nsell s1 u3 {max} 9 1\nsell s2 u4 {max} 8 1"
+        );
+        for strategy in [MatchStrategy::Greedy, MatchStrategy::MaxVolume] {
+            let settings = MatchSettings {
+                strategy,
+                ..Default::default()
+            };
+            let (buys, sells) = book(&fixture);
+            let outcome = compute_matches(buys.clone(), sells.clone(), &settings);
+            assert_eq!(outcome.unmatched_buy_volume, max);
+            assert_eq!(outcome.unmatched_sell_volume, max);
+
+            // Nothing to cross against takes the early return.
+            let outcome = compute_matches(buys, BinaryHeap::new(), &settings);
+            assert_eq!(outcome.unmatched_buy_volume, max);
+        }
+    }
+
+    #[test]
+    fn total_amount_saturates() {
+        let max = u128::MAX;
+        let fixture =
+            format!("buy b1 u1 {max} 10 1\nsell s1 u2 {max} 9 1\nsell s2 u3 {max} 9 2\nbuy b2 u4 {max} 10 2");
+        for strategy in [MatchStrategy::Greedy, MatchStrategy::MaxVolume] {
+            let (buys, sells) = book(&fixture);
+            let settings = MatchSettings {
+                strategy,
+                ..Default::default()
+            };
+            let outcome = compute_matches(buys, sells, &settings);
+            assert_eq!(outcome.matches.len(), 2);
+            assert_eq!(outcome.total_amount, max);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Delays and failures injected in front of every market call, to exercise
+/// the retry, standby and confirmation paths against a healthy node. Only
+/// built with the `fault-injection` feature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultConfig {
+    /// Probability in `0.0..=1.0` that a call fails without being sent.
+    pub failure_rate: f64,
+    pub delay: Option<Duration>,
+    pub seed: Option<u64>,
+}
+
+pub struct FaultInjector {
+    pub config: FaultConfig,
+    rng: Mutex<StdRng>,
+}
+
+impl FaultInjector {
+    /// A fixed `seed` makes the sequence of failures reproducible.
+    pub fn new(config: FaultConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            config,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Sleeps for the configured delay, then returns an error for the calls
+    /// picked to fail.
+    pub async fn inject(&self) -> Result<(), String> {
+        if let Some(delay) = self.config.delay {
+            tokio::time::sleep(delay).await;
+        }
+        let failure_rate = self.config.failure_rate.clamp(0.0, 1.0);
+        if self.rng.lock().unwrap().gen_bool(failure_rate) {
+            return Err("injected failure".to_string());
+        }
+        Ok(())
+    }
+}
@@ -1,226 +1,1123 @@
-use crate::config::ev;
+use super::chunking::{cap_chunks, chunk_matches, order_chunks, prioritize};
+use super::confirm::{wait_for_confirmation, Confirmation, ReorgPolicy};
+use super::counterparty::require_counterparties;
+use super::crossing::{compute_matches, CrossingOutcome, MatchSettings};
+#[cfg(feature = "fault-injection")]
+use super::fault::FaultInjector;
+use super::onchain::clamp_to_onchain;
+use super::price_band::PriceBand;
+use super::quarantine::Quarantine;
+use super::retry::Backoff;
+use super::signing::{is_signing_error, PulledWallets};
+use super::topup::{NoopTopup, TopupProvider};
+use super::volume_guard::VolumeGuard;
+use crate::config::{Config, MatcherConfig};
 use crate::error::Error;
-use crate::logger::{log_transactions, TransactionLog};
+use crate::logger::{
+    log_transactions, prune_transactions, LogSink, LogSinkKind, NoopSink, PostgresSink, StdoutSink,
+    TransactionLog,
+};
 use crate::management::manager::OrderManager;
-use crate::model::SpotOrder;
-use fuels::types::Bits256;
-use fuels::{accounts::provider::Provider, accounts::wallet::WalletUnlocked, types::ContractId};
-use log::{error, info};
+use crate::metrics::Metrics;
+use crate::model::{MatchRecord, OrderType, SpotOrder};
+use crate::util::telemetry::CycleSpan;
+use chrono::Utc;
+use fuels::accounts::ViewOnlyAccount;
+use fuels::types::{AssetId, Bits256};
+use fuels::{accounts::provider::Provider, accounts::wallet::WalletUnlocked};
+use futures_util::future::BoxFuture;
+use log::{debug, error, info, warn};
+use opentelemetry::global;
 use spark_market_sdk::MarketContract;
 use sqlx::PgPool;
 use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashSet};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use tokio::time::Instant;
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use uuid::Uuid;
+
+/// How a failed submission affects the cycle.
+///
+/// `FailFast` clears the book and returns the error from `match_orders`.
+/// `BestEffort` logs the failure, keeps the orders for the next cycle and
+/// never turns it into a cycle `Err`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CycleMode {
+    #[default]
+    FailFast,
+    BestEffort,
+}
+
+impl FromStr for CycleMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "FailFast" => Ok(CycleMode::FailFast),
+            "BestEffort" => Ok(CycleMode::BestEffort),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown cycle mode {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// What a cycle's chunks have filled so far and how many failed.
+#[derive(Debug, Default)]
+struct CycleProgress {
+    matched_ids: HashSet<String>,
+    filled: Vec<MatchRecord>,
+    failed_chunks: usize,
+}
+
+impl CycleProgress {
+    fn submitted(&mut self, chunk_ids: Vec<String>, chunk: &[MatchRecord]) {
+        self.matched_ids.extend(chunk_ids);
+        self.filled.extend_from_slice(chunk);
+    }
+
+    /// Counts a failed chunk. Returns `true` if `mode` ends the cycle on it.
+    fn failed(&mut self, mode: CycleMode) -> bool {
+        self.failed_chunks += 1;
+        mode == CycleMode::FailFast
+    }
+}
+
+/// Which market call submits a chunk.
+///
+/// `Many` sends each chunk's unique order ids to `match_order_many`. `Pair`
+/// sends every match on its own as a `match_order_pair(buy, sell)` call,
+/// so chunking settings are ignored and each match is one chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchCall {
+    #[default]
+    Many,
+    Pair,
+}
+
+impl FromStr for MatchCall {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Many" => Ok(MatchCall::Many),
+            "Pair" => Ok(MatchCall::Pair),
+            _ => Err(Error::StringParsingError(format!(
+                "unknown match call {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// What a cycle submitted, handed to the `on_cycle` callback.
+#[derive(Debug, Clone)]
+pub struct MatchReport {
+    pub cycle_id: String,
+    /// Every match crossed, submitted or not.
+    pub matches: Vec<MatchRecord>,
+    pub total_amount: u128,
+    /// Ids of the orders in chunks that were submitted successfully.
+    pub matched_ids: HashSet<String>,
+    pub failed_chunks: usize,
+    pub deferred_chunks: usize,
+}
+
+pub type CycleCallback = Box<dyn Fn(MatchReport) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Shared flag that stops cycles from crossing and submitting while set.
+/// The book and the feed keep running, so matching resumes on a fresh book.
+#[derive(Debug, Clone, Default)]
+pub struct PauseSwitch(Arc<AtomicBool>);
+
+impl PauseSwitch {
+    pub fn pause(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A wallet and the market contract bound to it.
+pub struct Submitter {
+    pub wallet: WalletUnlocked,
+    pub market: MarketContract,
+}
 
 pub struct SparkMatcher {
     pub order_manager: Arc<OrderManager>,
+    pub metrics: Arc<Metrics>,
     pub market: MarketContract,
+    pub wallet: WalletUnlocked,
+    pub base_asset_id: AssetId,
+    /// Takes over submissions once the primary wallet has failed
+    /// `standby_failure_threshold` chunks in a row, for good or until
+    /// `primary_cooldown` has passed.
+    pub standby: Option<Submitter>,
+    pub standby_active: AtomicBool,
+    pub standby_since: std::sync::Mutex<Option<Instant>>,
+    pub consecutive_failures: AtomicU32,
+    pub topup: Box<dyn TopupProvider>,
     pub log_sender: mpsc::UnboundedSender<TransactionLog>,
     pub last_receive_time: Arc<tokio::sync::Mutex<Instant>>,
+    pub config: MatcherConfig,
+    /// Called with the report of every cycle that crossed something. Runs
+    /// on its own task so a slow callback never delays the next cycle.
+    pub on_cycle: Option<CycleCallback>,
+    pub paused: PauseSwitch,
+    pub volume_guard: Option<std::sync::Mutex<VolumeGuard>>,
+    pub price_band: Option<std::sync::Mutex<PriceBand>>,
+    pub backoff: Backoff,
+    /// Ids of orders from recently failed chunks, bounded by
+    /// `retry_queue_size`. Their matches go first in the next cycle.
+    pub retry_queue: std::sync::Mutex<VecDeque<String>>,
+    pub quarantine: Option<std::sync::Mutex<Quarantine>>,
+    #[cfg(feature = "fault-injection")]
+    pub fault_injector: Option<FaultInjector>,
 }
 
 impl SparkMatcher {
-    pub async fn new(order_manager: Arc<OrderManager>) -> Result<Self, Error> {
-        let provider = Provider::connect("testnet.fuel.network").await?;
-        let mnemonic = ev("MNEMONIC")?;
-        let contract_id = ev("CONTRACT_ID")?;
+    pub async fn new(
+        order_manager: Arc<OrderManager>,
+        metrics: Arc<Metrics>,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let provider =
+            connect_provider(&config.provider_url, config.provider_connect_timeout).await?;
         let wallet =
-            WalletUnlocked::new_from_mnemonic_phrase(&mnemonic, Some(provider.clone())).unwrap();
-        let market = MarketContract::new(ContractId::from_str(&contract_id)?, wallet).await;
+            WalletUnlocked::new_from_mnemonic_phrase(&config.mnemonic, Some(provider.clone()))
+                .unwrap();
+        let base_asset_id = *provider.base_asset_id();
+        let market = MarketContract::new(config.contract_id, wallet.clone()).await;
+        let standby = match &config.standby_mnemonic {
+            Some(mnemonic) => {
+                let wallet =
+                    WalletUnlocked::new_from_mnemonic_phrase(mnemonic, Some(provider.clone()))
+                        .unwrap();
+                let market = MarketContract::new(config.contract_id, wallet.clone()).await;
+                Some(Submitter { wallet, market })
+            }
+            None => None,
+        };
 
-        let database_url = ev("DATABASE_URL")?;
-        let db_pool = PgPool::connect(&database_url).await.unwrap();
+        let sink: Box<dyn LogSink> = match config.log_sink {
+            LogSinkKind::Postgres => {
+                // Config requires DATABASE_URL for the Postgres sink.
+                let database_url = config.database_url.as_ref().unwrap();
+                let db_pool = PgPool::connect(database_url).await.unwrap();
+                if let Some(max_age) = config.log_retention {
+                    tokio::spawn(prune_transactions(
+                        db_pool.clone(),
+                        max_age,
+                        config.log_retention_interval,
+                    ));
+                }
+                Box::new(PostgresSink { db_pool })
+            }
+            LogSinkKind::Stdout => Box::new(StdoutSink),
+            LogSinkKind::Noop => Box::new(NoopSink),
+        };
 
         let (log_sender, log_receiver) = mpsc::unbounded_channel();
-        tokio::spawn(log_transactions(log_receiver, db_pool));
+        tokio::spawn(log_transactions(
+            log_receiver,
+            sink,
+            config.log_aggregate_window,
+        ));
 
         Ok(Self {
             order_manager,
+            metrics,
             market,
+            wallet,
+            base_asset_id,
+            standby,
+            standby_active: AtomicBool::new(false),
+            standby_since: std::sync::Mutex::new(None),
+            consecutive_failures: AtomicU32::new(0),
+            topup: Box::new(NoopTopup),
             log_sender,
             last_receive_time: Arc::new(tokio::sync::Mutex::new(Instant::now())),
+            config: config.matcher.clone(),
+            on_cycle: None,
+            paused: PauseSwitch::default(),
+            volume_guard: config.matcher.volume_anomaly_multiple.map(|multiple| {
+                std::sync::Mutex::new(VolumeGuard::new(
+                    multiple,
+                    config.matcher.volume_anomaly_window,
+                ))
+            }),
+            price_band: config.matcher.price_band.map(|band| {
+                std::sync::Mutex::new(PriceBand::new(band, config.matcher.price_band_window))
+            }),
+            backoff: Backoff::new(
+                config.matcher.retry_backoff,
+                config.matcher.retry_jitter,
+                config.matcher.retry_seed,
+            ),
+            retry_queue: std::sync::Mutex::new(VecDeque::new()),
+            quarantine: config
+                .matcher
+                .poison_order_threshold
+                .map(|threshold| std::sync::Mutex::new(Quarantine::new(threshold))),
+            #[cfg(feature = "fault-injection")]
+            fault_injector: config.matcher.fault.map(FaultInjector::new),
         })
     }
 
+    pub fn set_on_cycle<F, Fut>(&mut self, callback: F)
+    where
+        F: Fn(MatchReport) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.on_cycle = Some(Box::new(move |report| Box::pin(callback(report))));
+    }
+
+    fn active_submitter(&self) -> (&WalletUnlocked, &MarketContract) {
+        if let Some(cooldown) = self.config.primary_cooldown {
+            let mut standby_since = self.standby_since.lock().unwrap();
+            if matches!(*standby_since, Some(since) if since.elapsed() >= cooldown) {
+                info!(
+                    "Primary wallet cooldown of {:?} over, switching back from standby",
+                    cooldown
+                );
+                *standby_since = None;
+                self.standby_active.store(false, Ordering::Relaxed);
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+            }
+        }
+
+        match &self.standby {
+            Some(standby) if self.standby_active.load(Ordering::Relaxed) => {
+                (&standby.wallet, &standby.market)
+            }
+            _ => (&self.wallet, &self.market),
+        }
+    }
+
+    /// The active submitter, or the other wallet if the active one was
+    /// pulled this cycle; `None` once every wallet has been pulled.
+    fn usable_submitter(
+        &self,
+        pulled: &PulledWallets,
+    ) -> Option<(&WalletUnlocked, &MarketContract)> {
+        let mut candidates = vec![self.active_submitter()];
+        match &self.standby {
+            Some(_) if self.standby_active.load(Ordering::Relaxed) => {
+                candidates.push((&self.wallet, &self.market))
+            }
+            Some(standby) => candidates.push((&standby.wallet, &standby.market)),
+            None => {}
+        }
+        pulled.first_usable(
+            candidates
+                .into_iter()
+                .map(|submitter| (submitter.0.address().to_string(), submitter)),
+        )
+    }
+
+    /// Counts a failed submission and promotes the standby wallet once the
+    /// primary has failed `standby_failure_threshold` times in a row.
+    fn record_submission_failure(&self, cycle_id: &str) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(standby) = &self.standby {
+            if failures >= self.config.standby_failure_threshold
+                && !self.standby_active.swap(true, Ordering::Relaxed)
+            {
+                warn!(
+                    "[cycle {}] Primary wallet failed {} submissions in a row, promoting standby {}",
+                    cycle_id,
+                    failures,
+                    standby.wallet.address()
+                );
+                *self.standby_since.lock().unwrap() = Some(Instant::now());
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Counts a failed chunk against each of its orders, quarantining the
+    /// ones that have now been in `poison_order_threshold` failed chunks in a
+    /// row.
+    fn record_order_failures(&self, cycle_id: &str, order_ids: &[String]) {
+        let Some(quarantine) = &self.quarantine else {
+            return;
+        };
+        let mut quarantine = quarantine.lock().unwrap();
+        for id in quarantine.record_failures(order_ids) {
+            warn!(
+                "[cycle {}] Order {} was in {} failed chunks in a row, quarantining it",
+                cycle_id, id, quarantine.threshold
+            );
+            self.metrics
+                .quarantined_orders
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn clear_order_failures(&self, order_ids: &[String]) {
+        if let Some(quarantine) = &self.quarantine {
+            quarantine.lock().unwrap().clear_failures(order_ids);
+        }
+    }
+
+    fn queue_retry(&self, order_ids: Vec<String>) {
+        let limit = self.config.retry_queue_size;
+        let mut queue = self.retry_queue.lock().unwrap();
+        queue.extend(order_ids);
+        while queue.len() > limit {
+            queue.pop_front();
+        }
+    }
+
+    fn report_cycle(&self, report: MatchReport) {
+        if let Some(callback) = &self.on_cycle {
+            tokio::spawn(callback(report));
+        }
+    }
+
+    /// Runs a cycle every `match_interval`. Cycles are awaited one at a time,
+    /// so a slow cycle never overlaps the next; ticks that fall inside it are
+    /// skipped and counted in `overlap_skipped_cycles`.
     pub async fn run(&self) -> Result<(), Error> {
+        let mut interval = tokio::time::interval(self.config.match_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
         loop {
+            interval.tick().await;
+            let cycle_start = Instant::now();
             if let Err(e) = self.match_orders().await {
                 error!("Error during matching orders: {:?}", e);
             }
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            let skipped =
+                cycle_start.elapsed().as_millis() / self.config.match_interval.as_millis().max(1);
+            if skipped > 0 {
+                warn!(
+                    "Cycle took {:?}, skipping {} overlapping cycle(s)",
+                    cycle_start.elapsed(),
+                    skipped
+                );
+                self.metrics
+                    .overlap_skipped_cycles
+                    .fetch_add(skipped as u64, Ordering::Relaxed);
+            }
         }
     }
 
     pub async fn match_orders(&self) -> Result<(), Error> {
+        if self.paused.is_paused() {
+            info!("Matching is paused, skipping cycle");
+            return Ok(());
+        }
+
+        if let Some(schedule) = &self.config.schedule {
+            let now = Utc::now();
+            if !schedule.is_active(now) {
+                info!("Outside the matching schedule at {}, skipping cycle", now);
+                return Ok(());
+            }
+        }
+
+        if let Some(max_lag) = self.config.max_feed_lag {
+            let lag = self.order_manager.feed_lag();
+            if feed_lagging(lag, max_lag) {
+                match lag {
+                    Some(lag) => warn!(
+                        "No order from the feed for {:?} (threshold {:?}), skipping cycle",
+                        lag, max_lag
+                    ),
+                    None => warn!("No order from the feed yet, skipping cycle"),
+                }
+                self.metrics
+                    .feed_lag_skipped_cycles
+                    .fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+
         let receive_time = {
             let mut last_receive_time = self.last_receive_time.lock().await;
             let duration = last_receive_time.elapsed();
             *last_receive_time = Instant::now();
+            if let Some(max_gap) = self.config.stale_cycle_gap {
+                if duration > max_gap {
+                    warn!(
+                        "No cycle for {:?} (threshold {:?}), the loop or the feed may have stalled",
+                        duration, max_gap
+                    );
+                    self.metrics.stale_cycles.fetch_add(1, Ordering::Relaxed);
+                }
+            }
             duration.as_millis() as i64
         };
 
-        info!("-----Trying to match orders");
+        let cycle_id = Uuid::new_v4().to_string();
+        info!("[cycle {}] -----Trying to match orders", cycle_id);
+        let tracer = global::tracer("spark-matcher");
+        let cycle_span = CycleSpan::start(&tracer, &cycle_id);
 
-        let match_start = Instant::now();
-        info!("Match start time: {:?}", match_start);
+        let book_hash = self.order_manager.book_hash().await;
+        self.metrics.cycles.fetch_add(1, Ordering::Relaxed);
+        self.metrics.book_hash.store(book_hash, Ordering::Relaxed);
+        info!("[cycle {}] Book hash: {:016x}", cycle_id, book_hash);
 
-        let mut buy_queue = BinaryHeap::new();
-        let mut sell_queue = BinaryHeap::new();
+        let match_start = Instant::now();
+        info!("[cycle {}] Match start time: {:?}", cycle_id, match_start);
 
-        {
+        // Orders held back by `min_order_age` survive a `FailFast` clear,
+        // so they keep their `received_at` until they are old enough.
+        let (buy_snapshot, sell_snapshot, resting): (
+            Vec<SpotOrder>,
+            Vec<SpotOrder>,
+            HashSet<String>,
+        ) = {
             let buy_orders = self.order_manager.buy_orders.read().await;
-            for (_, orders) in buy_orders.iter() {
-                for order in orders {
-                    buy_queue.push(order.clone());
-                }
+            let sell_orders = self.order_manager.sell_orders.read().await;
+
+            let book_orders: usize = buy_orders
+                .values()
+                .chain(sell_orders.values())
+                .map(Vec::len)
+                .sum();
+            if book_orders < self.config.min_book_orders {
+                info!(
+                    "[cycle {}] Skipping cycle: {} orders in book, need {}",
+                    cycle_id, book_orders, self.config.min_book_orders
+                );
+                return Ok(());
             }
 
-            let sell_orders = self.order_manager.sell_orders.read().await;
-            for (_, orders) in sell_orders.iter() {
-                for order in orders {
-                    sell_queue.push(Reverse(order.clone()));
-                }
+            if buy_orders.is_empty() || sell_orders.is_empty() {
+                info!(
+                    "[cycle {}] Skipping cycle: one side of the book is empty",
+                    cycle_id
+                );
+                return Ok(());
             }
+
+            let now_ms = Utc::now().timestamp_millis();
+            let rested = |order: &SpotOrder| match self.config.min_order_age {
+                Some(age) => now_ms - order.received_at >= age.as_millis() as i64,
+                None => true,
+            };
+            let quarantine = self.quarantine.as_ref().map(|q| q.lock().unwrap());
+            let eligible = |order: &&SpotOrder| {
+                if quarantine.as_ref().is_some_and(|q| q.contains(&order.id)) {
+                    return false;
+                }
+                let whitelisted = match &self.config.order_whitelist {
+                    Some(ids) => ids.contains(&order.id),
+                    None => true,
+                };
+                whitelisted && rested(*order)
+            };
+            (
+                buy_orders
+                    .values()
+                    .flatten()
+                    .filter(eligible)
+                    .cloned()
+                    .collect(),
+                sell_orders
+                    .values()
+                    .flatten()
+                    .filter(eligible)
+                    .cloned()
+                    .collect(),
+                buy_orders
+                    .values()
+                    .chain(sell_orders.values())
+                    .flatten()
+                    .filter(|order| !rested(*order))
+                    .map(|order| order.id.clone())
+                    .collect(),
+            )
+        };
+        let snapshot_time = match_start.elapsed().as_millis() as i64;
+
+        let owners: HashMap<String, String> = match self.config.min_counterparties {
+            Some(_) => buy_snapshot
+                .iter()
+                .chain(&sell_snapshot)
+                .map(|order| (order.id.clone(), order.user.clone()))
+                .collect(),
+            None => HashMap::new(),
+        };
+        let booked_amounts: HashMap<String, u128> = if self.config.verify_fills {
+            buy_snapshot
+                .iter()
+                .chain(&sell_snapshot)
+                .map(|order| (order.id.clone(), order.amount))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        let heap_start = Instant::now();
+        let buy_queue: BinaryHeap<SpotOrder> = buy_snapshot.into();
+        let sell_queue: BinaryHeap<Reverse<SpotOrder>> =
+            sell_snapshot.into_iter().map(Reverse).collect();
+        let heap_build_time = heap_start.elapsed().as_millis() as i64;
+
+        let CrossingOutcome {
+            matches,
+            total_amount,
+            remaining_buys,
+            remaining_sells,
+            unmatched_buy_volume,
+            unmatched_sell_volume,
+            skips,
+            self_trade_cancelled,
+            buyer_improvement,
+            seller_improvement,
+            maker_rebates,
+            taker_fees,
+        } = compute_matches(buy_queue, sell_queue, &self.config.settings);
+
+        let match_duration = match_start.elapsed().as_millis() as i64;
+        info!(
+            "[cycle {}] Match duration calculated: {} (snapshot {}, heap build {})",
+            cycle_id, match_duration, snapshot_time, heap_build_time
+        );
+        let prep_start = Instant::now();
+
+        info!(
+            "[cycle {}] Price improvement: {} to buyers, {} to sellers",
+            cycle_id, buyer_improvement, seller_improvement
+        );
+
+        if self.config.settings.fees.is_some() {
+            info!(
+                "[cycle {}] Fees: {} maker rebates, {} taker fees, {} net",
+                cycle_id,
+                maker_rebates,
+                taker_fees,
+                taker_fees as i128 - maker_rebates as i128
+            );
+            Metrics::saturating_add(&self.metrics.maker_rebates, maker_rebates);
+            Metrics::saturating_add(&self.metrics.taker_fees, taker_fees);
         }
 
-        let mut matches: Vec<(String, String, u128)> = Vec::new();
-        let mut total_amount: u128 = 0;
+        info!(
+            "[cycle {}] Unmatched volume: {} buy, {} sell; skips: {:?}",
+            cycle_id, unmatched_buy_volume, unmatched_sell_volume, skips
+        );
 
-        while let (Some(mut buy_order), Some(Reverse(mut sell_order))) =
-            (buy_queue.pop(), sell_queue.pop())
-        {
-            if buy_order.price >= sell_order.price {
-                let match_amount = std::cmp::min(buy_order.amount, sell_order.amount);
-                matches.push((buy_order.id.clone(), sell_order.id.clone(), match_amount));
-                total_amount += match_amount;
+        if !self_trade_cancelled.is_empty() {
+            info!(
+                "[cycle {}] Self-trade prevention took {} order(s) out of the cycle: {:?}",
+                cycle_id,
+                self_trade_cancelled.len(),
+                self_trade_cancelled
+            );
+        }
 
-                buy_order.amount -= match_amount;
-                sell_order.amount -= match_amount;
+        let (matches, total_amount) = if self.config.clamp_to_onchain && !matches.is_empty() {
+            let onchain = self.fetch_onchain_amounts(&cycle_id, &matches).await;
+            let matches = clamp_to_onchain(matches, &onchain);
+            let total_amount = matches.iter().map(|m| m.amount).sum();
+            (matches, total_amount)
+        } else {
+            (matches, total_amount)
+        };
 
-                if buy_order.amount > 0 {
-                    buy_queue.push(buy_order);
-                }
+        let (matches, total_amount) = match self.config.min_counterparties {
+            Some(min) if !matches.is_empty() => {
+                let matches = require_counterparties(matches, &owners, min);
+                let total_amount = matches.iter().map(|m| m.amount).sum();
+                (matches, total_amount)
+            }
+            _ => (matches, total_amount),
+        };
 
-                if sell_order.amount > 0 {
-                    sell_queue.push(Reverse(sell_order));
+        let (matches, total_amount) = match &self.price_band {
+            Some(band) if !matches.is_empty() => {
+                let (matches, outside) = band.lock().unwrap().filter(matches);
+                if !outside.is_empty() {
+                    warn!(
+                        "[cycle {}] Deferring {} match(es) priced outside the band: {:?}",
+                        cycle_id,
+                        outside.len(),
+                        outside
+                    );
                 }
-            } else {
-                sell_queue.push(Reverse(sell_order));
+                let total_amount = matches
+                    .iter()
+                    .map(|m| m.amount)
+                    .fold(0, u128::saturating_add);
+                (matches, total_amount)
             }
-        }
-
-        let match_duration = match_start.elapsed().as_millis() as i64;
-        info!("Match duration calculated: {}", match_duration);
+            _ => (matches, total_amount),
+        };
 
         let matches_len = matches.len();
         if matches_len == 0 {
             return Ok(());
         }
 
-        let post_start = Instant::now();
-        info!("Post start time: {:?}", post_start);
+        if let Some(guard) = &self.volume_guard {
+            if guard.lock().unwrap().check(total_amount) {
+                error!(
+                    "[cycle {}] 🚨 Abnormal cycle volume {} across {} matches, withholding submission and pausing matching until resumed",
+                    cycle_id, total_amount, matches_len
+                );
+                self.paused.pause();
+                return Ok(());
+            }
+        }
+
+        let retry_ids: HashSet<String> = self.retry_queue.lock().unwrap().drain(..).collect();
+        let matches = if retry_ids.is_empty() {
+            matches
+        } else {
+            info!(
+                "[cycle {}] Submitting matches of {} order(s) from failed chunks first",
+                cycle_id,
+                retry_ids.len()
+            );
+            prioritize(matches, &retry_ids)
+        };
+
+        let tag_volume = tag_volume(&matches);
+        if !tag_volume.is_empty() {
+            info!("[cycle {}] Volume by tag: {:?}", cycle_id, tag_volume);
+        }
+
+        let unique_order_ids = unique_order_ids(&matches);
+        debug!("[cycle {}] Matches: {:?}", cycle_id, matches);
 
-        let unique_order_ids: HashSet<String> = matches
-            .iter()
-            .flat_map(|(buy_id, sell_id, _)| vec![buy_id.clone(), sell_id.clone()])
-            .collect();
+        self.ensure_gas_balance(&cycle_id).await;
 
-        let unique_bits256_ids: Vec<Bits256> = unique_order_ids
-            .iter()
-            .map(|id| Bits256::from_hex_str(id).unwrap())
-            .collect();
-        /*
-                    let formatted_log = self.format_order_info(
-                        &matches.iter().map(|(buy_id, _, amount)| (buy_id.clone(), format!("Price: TBD"), *amount)).collect::<Vec<_>>(),
-                        &matches.iter().map(|(_, sell_id, amount)| (sell_id.clone(), format!("Price: TBD"), *amount)).collect::<Vec<_>>()
+        let chunks = match self.config.match_call {
+            MatchCall::Many => {
+                chunk_matches(&matches, self.config.chunk_by, self.config.chunk_size)
+            }
+            MatchCall::Pair => matches.chunks(1).collect(),
+        };
+        let mut chunks = cap_chunks(
+            chunks,
+            self.config.max_chunk_amount,
+            self.config.max_chunk_ids,
+        );
+        order_chunks(&mut chunks, self.config.chunk_order);
+        info!(
+            "[cycle {}] Submitting {} matches ({} orders, total amount {}) in {} chunk(s)",
+            cycle_id,
+            matches_len,
+            unique_order_ids.len(),
+            total_amount,
+            chunks.len()
+        );
+
+        let mut progress = CycleProgress::default();
+        let mut tx_ids: Vec<String> = Vec::new();
+        let mut deferred_chunks = 0;
+        let mut unconfirmed_chunks = 0;
+        let mut reorged = false;
+        let mut pulled = PulledWallets::default();
+        let chunk_count = chunks.len();
+        let prep_time = prep_start.elapsed().as_millis() as i64;
+        info!("[cycle {}] Prep duration: {}", cycle_id, prep_time);
+
+        for (n, chunk) in chunks.into_iter().enumerate() {
+            if reorged {
+                deferred_chunks = chunk_count - n;
+                warn!(
+                    "[cycle {}] Deferring {} chunk(s) to the next cycle after a chain rollback",
+                    cycle_id, deferred_chunks
+                );
+                break;
+            }
+
+            if let Some(budget) = self.config.cycle_budget {
+                if n > 0 && match_start.elapsed() > budget {
+                    deferred_chunks = chunk_count - n;
+                    warn!(
+                        "[cycle {}] Cycle budget {:?} exceeded, deferring {} chunk(s) to the next cycle",
+                        cycle_id, budget, deferred_chunks
                     );
-                    info!("Matched Orders:\n{}", formatted_log);
-
-                    let matches_human: Vec<String> = matches.clone()
-                        .into_iter()
-                        .flat_map(|(buy_id, sell_id, _)| vec![buy_id, sell_id])
-                        .collect();
-
-                    let matches: Vec<Bits256> = matches
-                        .into_iter()
-                        .flat_map(|(buy_id, sell_id, _)| vec![Bits256::from_hex_str(&buy_id).unwrap(), Bits256::from_hex_str(&sell_id).unwrap()])
-                        .collect();
-        */
-        println!("=================================================");
-        println!("=================================================");
-        println!("matches {:?}", matches);
-        println!("=================================================");
-        println!("=================================================");
-        let res = self.market.match_order_many(unique_bits256_ids).await;
-        self.order_manager.clear_orders().await;
-        /*
-        let a = Bits256::from_hex_str("0x7e9927af85019fa02bc244477f72cb132a7a8b8ea6becf0e30f8a042de2f5397").unwrap();
-        let b = Bits256::from_hex_str("0x48b64d43c40f3a70617475d345bfd709c233e43e3c78e44be42efb77a849f8bd").unwrap();
-
-        println!("=======================================");
-        println!("=======================================");
-        println!("=======================================");
-        let r = self.market.order(b).await.unwrap().value;
-        println!("{:?}", r);
-        let r1 = self.market.order_change_info(b).await.unwrap().value;
-        println!("=======================================");
-        //let r = self.market.order(b).await;
-        println!("{:?}", r1);
-        println!("=======================================");
-        println!("=======================================");
-        */
-
-        match res {
-            Ok(r) => {
-                let post_duration = post_start.elapsed().as_millis() as i64;
-                let log = TransactionLog {
-                    total_amount,
-                    matches_len,
-                    tx_id: r.tx_id.unwrap().to_string(),
-                    gas_used: r.gas_used,
-                    match_time_ms: match_duration,
-                    buy_orders: buy_queue.len(),
-                    sell_orders: sell_queue.len(),
-                    receive_time_ms: receive_time,
-                    post_time_ms: post_duration,
+                    self.metrics
+                        .budget_exceeded_cycles
+                        .fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+            }
+
+            // After the breaks above, so it only delays a chunk about to go out.
+            if let Some(delay) = self.config.chunk_delay {
+                if n > 0 {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+
+            let post_start = Instant::now();
+            info!("[cycle {}] Post start time: {:?}", cycle_id, post_start);
+
+            let chunk_ids = unique_order_ids(chunk);
+            let chunk_span = cycle_span.chunk(&tracer, n, chunk);
+            let chunk_bits256_ids: Vec<Bits256> = chunk_ids
+                .iter()
+                .map(|id| Bits256::from_hex_str(id).unwrap())
+                .collect();
+
+            let mut attempt = 0;
+            let mut wallet_address = "none".to_string();
+            let result = loop {
+                let Some((wallet, market)) = self.usable_submitter(&pulled) else {
+                    break Err("every wallet failed to sign this cycle".to_string());
+                };
+                wallet_address = wallet.address().to_string();
+                #[cfg(feature = "fault-injection")]
+                let injected = match &self.fault_injector {
+                    Some(injector) => injector.inject().await.err(),
+                    None => None,
                 };
-                info!("Logging transaction: {:?}", log);
-                self.log_sender.send(log).unwrap();
+                #[cfg(not(feature = "fault-injection"))]
+                let injected: Option<String> = None;
+                let call = match (injected, self.config.match_call) {
+                    (Some(reason), _) => Err(reason),
+                    (None, MatchCall::Many) => market
+                        .match_order_many(chunk_bits256_ids.clone())
+                        .await
+                        .map_err(|e| e.to_string()),
+                    (None, MatchCall::Pair) => market
+                        .match_order_pair(
+                            Bits256::from_hex_str(&chunk[0].buy_id).unwrap(),
+                            Bits256::from_hex_str(&chunk[0].sell_id).unwrap(),
+                        )
+                        .await
+                        .map_err(|e| e.to_string()),
+                };
+                match call {
+                    // Tried again on the other wallet without using up a retry.
+                    Err(e) if is_signing_error(&e) => {
+                        warn!(
+                            "[cycle {}] Wallet {} failed to sign chunk {} (`{}`), pulling it from rotation for the cycle",
+                            cycle_id, wallet_address, n, e
+                        );
+                        self.metrics
+                            .signing_failures
+                            .fetch_add(1, Ordering::Relaxed);
+                        pulled.pull(&wallet_address);
+                    }
+                    Err(e) if attempt < self.config.chunk_retries => {
+                        let delay = self.backoff.delay(attempt);
+                        warn!(
+                            "[cycle {}] Chunk {} failed on wallet {} (`{}`), retry {} in {:?}",
+                            cycle_id,
+                            n,
+                            wallet_address,
+                            e,
+                            attempt + 1,
+                            delay
+                        );
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    result => break result,
+                }
+            };
+
+            let result = match (result, self.config.confirmation) {
+                (Ok(r), Some(wait)) if r.tx_id.is_some() => {
+                    let tx_id = r.tx_id.unwrap();
+                    let (wallet, _) = self.active_submitter();
+                    let confirmation = match wallet.provider() {
+                        Some(provider) => wait_for_confirmation(provider, &tx_id, &wait).await,
+                        None => Confirmation::Uncertain,
+                    };
+                    match confirmation {
+                        Confirmation::Confirmed => Ok(r),
+                        Confirmation::Failed(reason) => {
+                            Err(format!("tx 0x{} failed on chain: {}", tx_id, reason))
+                        }
+                        Confirmation::Uncertain => {
+                            warn!(
+                                "[cycle {}] Chunk {} (tx 0x{}, wallet {}) not confirmed within {:?}, outcome uncertain; keeping its orders",
+                                cycle_id, n, tx_id, wallet_address, wait.timeout
+                            );
+                            self.metrics
+                                .unconfirmed_chunks
+                                .fetch_add(1, Ordering::Relaxed);
+                            unconfirmed_chunks += 1;
+                            continue;
+                        }
+                        Confirmation::Reorged { included_at } => {
+                            warn!(
+                                "[cycle {}] Block {} including chunk {} (tx 0x{}, wallet {}) was rolled back",
+                                cycle_id, included_at, n, tx_id, wallet_address
+                            );
+                            self.metrics.reorgs.fetch_add(1, Ordering::Relaxed);
+                            self.metrics
+                                .unconfirmed_chunks
+                                .fetch_add(1, Ordering::Relaxed);
+                            unconfirmed_chunks += 1;
+                            reorged = true;
+                            continue;
+                        }
+                    }
+                }
+                (result, _) => result,
+            };
+
+            match result {
+                Ok(r) => {
+                    self.consecutive_failures.store(0, Ordering::Relaxed);
+                    let post_duration = post_start.elapsed().as_millis() as i64;
+                    let tx_id = r.tx_id.unwrap().to_string();
+                    chunk_span.submitted(&tx_id);
+                    tx_ids.push(tx_id.clone());
+                    let log = TransactionLog {
+                        cycle_id: cycle_id.clone(),
+                        total_amount: chunk.iter().map(|m| m.amount).fold(0, u128::saturating_add),
+                        matches_len: chunk.len(),
+                        tx_id: tx_id.clone(),
+                        gas_used: r.gas_used,
+                        match_time_ms: match_duration,
+                        snapshot_time_ms: snapshot_time,
+                        heap_build_time_ms: heap_build_time,
+                        prep_time_ms: prep_time,
+                        buy_orders: remaining_buys,
+                        sell_orders: remaining_sells,
+                        unmatched_buy_volume,
+                        unmatched_sell_volume,
+                        skips,
+                        receive_time_ms: receive_time,
+                        post_time_ms: post_duration,
+                    };
+                    info!(
+                        "[cycle {}] Logging transaction from wallet {}: {:?}",
+                        cycle_id, wallet_address, log
+                    );
+                    self.log_sender.send(log).unwrap();
+                    info!(
+                        "[cycle {}] ✅✅✅ Matched {} orders from wallet {}\nhttps://app.fuel.network/tx/0x{}/simple\n",
+                        cycle_id,
+                        chunk.len(),
+                        wallet_address,
+                        tx_id,
+                    );
+                    self.clear_order_failures(&chunk_ids);
+                    progress.submitted(chunk_ids, chunk);
+                    if self.config.verify_fills {
+                        self.verify_fills(&cycle_id, n, chunk, &progress.filled, &booked_amounts)
+                            .await;
+                    }
+                }
+                Err(e) => {
+                    chunk_span.failed(&e);
+                    let e = Error::ChunkFailed {
+                        cycle_id: cycle_id.clone(),
+                        chunk: n,
+                        reason: e,
+                        matches: chunk.to_vec(),
+                    };
+                    error!(
+                        "[cycle {}] matching error from wallet {} `{}`\n",
+                        cycle_id, wallet_address, e
+                    );
+                    self.record_submission_failure(&cycle_id);
+                    self.record_order_failures(&cycle_id, &chunk_ids);
+                    if progress.failed(self.config.cycle_mode) {
+                        self.order_manager.clear_orders_except(&resting).await;
+                        cycle_span.finish(&matches, &tx_ids);
+                        self.report_cycle(MatchReport {
+                            cycle_id: cycle_id.clone(),
+                            matches: matches.clone(),
+                            total_amount,
+                            matched_ids: progress.matched_ids,
+                            failed_chunks: progress.failed_chunks,
+                            deferred_chunks: chunk_count - n - 1,
+                        });
+                        return Err(e);
+                    }
+                    self.queue_retry(chunk_ids);
+                }
+            }
+        }
+
+        let resync = reorged
+            && self.config.confirmation.map(|wait| wait.on_reorg) == Some(ReorgPolicy::Resync);
+        if resync {
+            // Fills confirmed earlier in the cycle may have been rolled back
+            // too, so the book is rebuilt instead of patched.
+            warn!(
+                "[cycle {}] Resyncing the book after a chain rollback",
+                cycle_id
+            );
+            self.order_manager.resync().await;
+        } else {
+            if progress.failed_chunks > 0 || deferred_chunks > 0 || unconfirmed_chunks > 0 {
                 info!(
-                    "✅✅✅ Matched {} orders\nhttps://app.fuel.network/tx/0x{}/simple\n",
-                    matches_len,
-                    r.tx_id.unwrap().to_string(),
+                    "[cycle {}] {} chunk(s) failed, {} deferred and {} unconfirmed, keeping their orders and the unfilled rest of submitted ones",
+                    cycle_id, progress.failed_chunks, deferred_chunks, unconfirmed_chunks
                 );
             }
-            Err(e) => {
-                error!("matching error `{}`\n", e);
-                return Err(Error::MatchOrdersError(e.to_string()));
-            }
-        };
+            // Only fills come off the book. Orders the crossing limits or the
+            // filters above held back were never submitted and stay for the
+            // next cycle.
+            self.order_manager.apply_fills(&progress.filled).await;
+        }
+
+        cycle_span.finish(&matches, &tx_ids);
+        self.report_cycle(MatchReport {
+            cycle_id,
+            matches,
+            total_amount,
+            matched_ids: progress.matched_ids,
+            failed_chunks: progress.failed_chunks,
+            deferred_chunks,
+        });
 
         Ok(())
     }
 
-    fn format_order_info(
+    /// Compares what is left on chain of every order in a submitted chunk
+    /// with what its booked amount minus this cycle's fills should leave,
+    /// logging and counting each difference.
+    async fn verify_fills(
+        &self,
+        cycle_id: &str,
+        chunk_index: usize,
+        chunk: &[MatchRecord],
+        filled: &[MatchRecord],
+        booked_amounts: &HashMap<String, u128>,
+    ) {
+        let onchain = self.fetch_onchain_amounts(cycle_id, chunk).await;
+        for (id, left) in onchain {
+            let Some(booked) = booked_amounts.get(&id) else {
+                continue;
+            };
+            let filled_amount: u128 = filled
+                .iter()
+                .filter(|record| record.buy_id == id || record.sell_id == id)
+                .map(|record| record.amount)
+                .sum();
+            let expected = booked.saturating_sub(filled_amount);
+            let actual = left.unwrap_or(0);
+            if actual != expected {
+                warn!(
+                    "[cycle {}] Chunk {}: order {} has {} left on chain, expected {} after filling {}",
+                    cycle_id, chunk_index, id, actual, expected, filled_amount
+                );
+                self.metrics
+                    .fill_discrepancies
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Remaining on-chain amount of every order in `matches`, `None` for
+    /// orders that no longer exist. Orders whose lookup fails are left out
+    /// and keep their local amount.
+    async fn fetch_onchain_amounts(
         &self,
-        buy_orders: &[(String, String, u128)],
-        sell_orders: &[(String, String, u128)],
-    ) -> String {
-        let mut logs = Vec::new();
-        logs.push("🔵 Buy Orders:".to_string());
-        for (id, price, amount) in buy_orders {
-            logs.push(format!("ID: {}, Price: {}, Amount: {}", id, price, amount));
+        cycle_id: &str,
+        matches: &[MatchRecord],
+    ) -> HashMap<String, Option<u128>> {
+        let (_, market) = self.active_submitter();
+        let mut onchain = HashMap::new();
+        for id in unique_order_ids(matches) {
+            let Ok(order_id) = Bits256::from_hex_str(&id) else {
+                continue;
+            };
+            match market.order(order_id).await {
+                Ok(response) => {
+                    let amount = response.value.map(|order| order.amount as u128);
+                    onchain.insert(id, amount);
+                }
+                Err(e) => warn!(
+                    "[cycle {}] Failed to read order {} on chain: {}",
+                    cycle_id, id, e
+                ),
+            }
         }
-        logs.push("🔴 Sell Orders:".to_string());
-        for (id, price, amount) in sell_orders {
-            logs.push(format!("ID: {}, Price: {}, Amount: {}", id, price, amount));
+        onchain
+    }
+
+    /// Asks the topup provider for a refill when the submission wallet's base
+    /// asset balance is under the configured floor.
+    async fn ensure_gas_balance(&self, cycle_id: &str) {
+        let Some(floor) = self.config.gas_balance_floor else {
+            return;
+        };
+
+        let (wallet, _) = self.active_submitter();
+        match wallet.get_asset_balance(&self.base_asset_id).await {
+            Ok(balance) if balance < floor => {
+                warn!(
+                    "[cycle {}] Wallet {} balance {} below floor {}, requesting topup of {}",
+                    cycle_id,
+                    wallet.address(),
+                    balance,
+                    floor,
+                    self.config.gas_topup_amount
+                );
+                self.topup.topup(wallet, self.config.gas_topup_amount).await;
+            }
+            Ok(_) => {}
+            Err(e) => error!("[cycle {}] Failed to read wallet balance: {}", cycle_id, e),
         }
+    }
+}
 
-        logs.join("\n")
+/// `Provider::connect` has no deadline of its own and hangs on an unreachable
+/// node, so startup bounds it here.
+async fn connect_provider(url: &str, timeout: Duration) -> Result<Provider, Error> {
+    match tokio::time::timeout(timeout, Provider::connect(url)).await {
+        Ok(Ok(provider)) => Ok(provider),
+        Ok(Err(e)) => Err(Error::ProviderConnect(url.to_string(), e.to_string())),
+        Err(_) => Err(Error::ProviderConnect(
+            url.to_string(),
+            format!("timed out after {:?}", timeout),
+        )),
+    }
+}
+
+/// Whether the feed's `lag` is past `max_lag`. A feed that never delivered
+/// an order counts as lagging.
+fn feed_lagging(lag: Option<Duration>, max_lag: Duration) -> bool {
+    match lag {
+        Some(lag) => lag > max_lag,
+        None => true,
+    }
+}
+
+/// Flattens `(buy, sell, amount, price)` matches into the id list
+/// `match_order_many` expects. The contract pairs the orders itself, so an
+/// order shared by many pairs (one large buy sweeping several small sells)
+/// is passed only once, in the order it was first matched:
+/// `[buy, sell_1, sell_2, ...]`.
+fn unique_order_ids(matches: &[MatchRecord]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    matches
+        .iter()
+        .flat_map(|m| [&m.buy_id, &m.sell_id])
+        .filter(|id| seen.insert(id.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Matched amount per order tag. A match counts toward both its buy's and
+/// its sell's tag; untagged orders are left out.
+fn tag_volume(matches: &[MatchRecord]) -> BTreeMap<&str, u128> {
+    let mut volume = BTreeMap::new();
+    for record in matches {
+        for tag in [&record.buy_tag, &record.sell_tag].into_iter().flatten() {
+            let tagged = volume.entry(tag.as_str()).or_default();
+            *tagged = u128::saturating_add(*tagged, record.amount);
+        }
     }
+    volume
 }
 
 impl OrderManager {
@@ -233,4 +1130,212 @@ impl OrderManager {
 
         (buy_list, sell_list)
     }
+
+    /// Total amount that would match right now under `settings`. Runs the
+    /// crossing on a copy of the book; nothing is mutated or submitted.
+    pub async fn crossable_volume(&self, settings: &MatchSettings) -> u128 {
+        let (buy_orders, sell_orders) = self.get_all_orders().await;
+        compute_matches(
+            buy_orders.into_iter().collect(),
+            sell_orders.into_iter().map(Reverse).collect(),
+            settings,
+        )
+        .total_amount
+    }
+
+    /// Projects how a hypothetical order would fill against the resting
+    /// opposite side under `settings`, as `(filled_amount, avg_price,
+    /// remaining)`. `avg_price` is volume-weighted over the fills and 0 when
+    /// nothing fills. The order is crossed against that side alone, so
+    /// orders of the book crossing each other do not count.
+    pub async fn simulate_order(
+        &self,
+        order: SpotOrder,
+        settings: &MatchSettings,
+    ) -> (u128, u128, u128) {
+        let amount = order.amount;
+        let outcome = match order.order_type {
+            OrderType::Buy => {
+                let sell_orders = self.get_all_sell_orders().await;
+                compute_matches(
+                    BinaryHeap::from(vec![order]),
+                    sell_orders.into_iter().map(Reverse).collect(),
+                    settings,
+                )
+            }
+            OrderType::Sell => {
+                let buy_orders = self.get_all_buy_orders().await;
+                compute_matches(
+                    buy_orders.into_iter().collect(),
+                    BinaryHeap::from(vec![Reverse(order)]),
+                    settings,
+                )
+            }
+        };
+
+        let filled = outcome.total_amount;
+        let notional = outcome.matches.iter().fold(0u128, |total, record| {
+            total.saturating_add(record.amount.saturating_mul(record.price))
+        });
+        let avg_price = notional.checked_div(filled).unwrap_or(0);
+        (filled, avg_price, amount.saturating_sub(filled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_cycles_while_the_feed_lags() {
+        let max_lag = Duration::from_secs(1);
+        assert!(feed_lagging(None, max_lag));
+        assert!(feed_lagging(Some(Duration::from_millis(1500)), max_lag));
+        assert!(!feed_lagging(Some(max_lag), max_lag));
+        assert!(!feed_lagging(Some(Duration::from_millis(200)), max_lag));
+    }
+
+    fn record(buy_id: &str, sell_id: &str, amount: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount,
+            price: 10,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn order(id: &str, order_type: OrderType, amount: u128, price: u128) -> SpotOrder {
+        SpotOrder {
+            id: id.to_string(),
+            user: format!("{}-owner", id),
+            asset: String::new(),
+            amount,
+            price,
+            timestamp: 1,
+            order_type,
+            tag: None,
+            received_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn simulate_order_projects_fills_without_touching_the_book() {
+        let manager = OrderManager::new();
+        for order in [
+            order("s1", OrderType::Sell, 5, 10),
+            order("s2", OrderType::Sell, 5, 12),
+            order("s3", OrderType::Sell, 10, 15),
+            order("b1", OrderType::Buy, 4, 9),
+        ] {
+            assert!(manager.add_order(order).await);
+        }
+        let settings = MatchSettings::default();
+
+        let buy = order("probe", OrderType::Buy, 10, 12);
+        assert_eq!(manager.simulate_order(buy, &settings).await, (10, 11, 0));
+
+        let sweep = order("probe", OrderType::Buy, 30, 20);
+        assert_eq!(manager.simulate_order(sweep, &settings).await, (20, 13, 10));
+
+        let sell = order("probe", OrderType::Sell, 10, 8);
+        assert_eq!(manager.simulate_order(sell, &settings).await, (4, 8, 6));
+
+        let below = order("probe", OrderType::Buy, 8, 9);
+        assert_eq!(manager.simulate_order(below, &settings).await, (0, 0, 8));
+
+        let (buys, sells) = manager.get_all_orders().await;
+        assert_eq!((buys.len(), sells.len()), (1, 3));
+    }
+
+    /// Runs `chunks` through a cycle's bookkeeping the way `match_orders`
+    /// does, `true` marking a chunk that was submitted.
+    fn settle(mode: CycleMode, chunks: &[(&[MatchRecord], bool)]) -> (CycleProgress, bool) {
+        let mut progress = CycleProgress::default();
+        for (chunk, submitted) in chunks {
+            if *submitted {
+                progress.submitted(unique_order_ids(chunk), chunk);
+            } else if progress.failed(mode) {
+                return (progress, true);
+            }
+        }
+        (progress, false)
+    }
+
+    #[tokio::test]
+    async fn cycle_modes_settle_a_mixed_chunk_set() {
+        let first = [record("b1", "s1", 5)];
+        let failing = [record("b2", "s2", 5)];
+        let last = [record("b3", "s3", 5)];
+        let chunks: [(&[MatchRecord], bool); 3] =
+            [(&first, true), (&failing, false), (&last, true)];
+        let buy_ids = |progress: &CycleProgress| -> Vec<String> {
+            progress.filled.iter().map(|m| m.buy_id.clone()).collect()
+        };
+
+        let (progress, stopped) = settle(CycleMode::FailFast, &chunks);
+        assert!(stopped);
+        assert_eq!(progress.failed_chunks, 1);
+        assert_eq!(buy_ids(&progress), ["b1"]);
+
+        let (progress, stopped) = settle(CycleMode::BestEffort, &chunks);
+        assert!(!stopped);
+        assert_eq!(progress.failed_chunks, 1);
+        assert_eq!(buy_ids(&progress), ["b1", "b3"]);
+        assert_eq!(progress.matched_ids.len(), 4);
+
+        // Only the fills come off the book, so the failed chunk's orders
+        // are there for the next cycle.
+        let manager = OrderManager::new();
+        manager
+            .add_orders(
+                ["b1", "b2", "b3"]
+                    .iter()
+                    .map(|id| order(id, OrderType::Buy, 5, 10))
+                    .chain(
+                        ["s1", "s2", "s3"]
+                            .iter()
+                            .map(|id| order(id, OrderType::Sell, 5, 10)),
+                    )
+                    .collect(),
+            )
+            .await;
+        manager.apply_fills(&progress.filled).await;
+        let (buys, sells) = manager.get_all_orders().await;
+        assert_eq!((buys.len(), sells.len()), (1, 1));
+        assert_eq!((buys[0].id.as_str(), sells[0].id.as_str()), ("b2", "s2"));
+    }
+
+    #[test]
+    fn unique_order_ids_keep_each_order_once_in_first_seen_order() {
+        let matches = [
+            record("b1", "s1", 10),
+            record("b1", "s2", 5),
+            record("b2", "s2", 3),
+            record("b2", "s1", 1),
+        ];
+        assert_eq!(unique_order_ids(&matches), ["b1", "s1", "s2", "b2"]);
+        assert!(unique_order_ids(&[]).is_empty());
+    }
+
+    #[test]
+    fn tag_volume_counts_both_sides_and_saturates() {
+        let mut mm_buy = record("b1", "s1", 30);
+        mm_buy.buy_tag = Some("mm".to_string());
+        mm_buy.sell_tag = Some("retail".to_string());
+        let mut mm_sell = record("b2", "s2", 5);
+        mm_sell.sell_tag = Some("mm".to_string());
+        let untagged = record("b3", "s3", 100);
+
+        let matches = [mm_buy.clone(), mm_sell, untagged];
+        assert_eq!(
+            tag_volume(&matches).into_iter().collect::<Vec<_>>(),
+            [("mm", 35), ("retail", 30)]
+        );
+
+        let mut huge = mm_buy;
+        huge.amount = u128::MAX;
+        assert_eq!(tag_volume(&[huge.clone(), huge])["mm"], u128::MAX);
+    }
 }
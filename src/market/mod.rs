@@ -1,3 +1,17 @@
+pub mod chunking;
+pub mod confirm;
+pub mod counterparty;
+pub mod crossing;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
 pub mod matcher;
+pub mod onchain;
+pub mod price_band;
+pub mod quarantine;
+pub mod retry;
+pub mod schedule;
+pub mod signing;
+pub mod topup;
+pub mod volume_guard;
 
 pub use matcher::SparkMatcher;
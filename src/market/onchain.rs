@@ -0,0 +1,121 @@
+use log::info;
+use std::collections::HashMap;
+
+use crate::model::MatchRecord;
+
+/// Clamps matches to what is left of each order on chain, where the local
+/// amount may be stale after fills by other takers. `onchain` maps an id to
+/// its remaining amount, or `None` when the order is gone; ids missing from
+/// the map keep their local amount. Matches are checked in order, so earlier
+/// ones use up an order first.
+pub fn clamp_to_onchain(
+    matches: Vec<MatchRecord>,
+    onchain: &HashMap<String, Option<u128>>,
+) -> Vec<MatchRecord> {
+    let mut remaining: HashMap<&str, u128> = onchain
+        .iter()
+        .filter_map(|(id, amount)| amount.map(|amount| (id.as_str(), amount)))
+        .collect();
+
+    let mut clamped = Vec::with_capacity(matches.len());
+    for mut record in matches {
+        let ids = [record.buy_id.as_str(), record.sell_id.as_str()];
+        if ids.iter().any(|id| matches!(onchain.get(*id), Some(None))) {
+            info!(
+                "Dropping match {} x {}: order no longer on chain",
+                record.buy_id, record.sell_id
+            );
+            continue;
+        }
+
+        let mut match_amount = record.amount;
+        for id in ids {
+            if let Some(left) = remaining.get(id) {
+                match_amount = match_amount.min(*left);
+            }
+        }
+        if match_amount == 0 {
+            info!(
+                "Dropping match {} x {}: filled on chain",
+                record.buy_id, record.sell_id
+            );
+            continue;
+        }
+        if match_amount < record.amount {
+            info!(
+                "Clamping match {} x {} from {} to {} on-chain",
+                record.buy_id, record.sell_id, record.amount, match_amount
+            );
+        }
+
+        for id in ids {
+            if let Some(left) = remaining.get_mut(id) {
+                *left -= match_amount;
+            }
+        }
+        record.amount = match_amount;
+        clamped.push(record);
+    }
+    clamped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(buy_id: &str, sell_id: &str, amount: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount,
+            price: 10,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn amounts(matches: &[MatchRecord]) -> Vec<(&str, &str, u128)> {
+        matches
+            .iter()
+            .map(|m| (m.buy_id.as_str(), m.sell_id.as_str(), m.amount))
+            .collect()
+    }
+
+    #[test]
+    fn clamps_stale_orders_and_uses_them_up_in_order() {
+        let onchain = HashMap::from([("s1".to_string(), Some(25))]);
+        let clamped = clamp_to_onchain(
+            vec![
+                record("b1", "s1", 20),
+                record("b2", "s1", 20),
+                record("b3", "s1", 20),
+            ],
+            &onchain,
+        );
+        assert_eq!(amounts(&clamped), [("b1", "s1", 20), ("b2", "s1", 5)]);
+    }
+
+    #[test]
+    fn orders_missing_from_the_map_keep_their_local_amount() {
+        let onchain = HashMap::from([("s1".to_string(), Some(100))]);
+        let clamped = clamp_to_onchain(vec![record("b1", "s1", 20)], &onchain);
+        assert_eq!(amounts(&clamped), [("b1", "s1", 20)]);
+
+        let clamped = clamp_to_onchain(vec![record("b1", "s1", 20)], &HashMap::new());
+        assert_eq!(amounts(&clamped), [("b1", "s1", 20)]);
+    }
+
+    #[test]
+    fn drops_matches_of_orders_gone_from_chain() {
+        let onchain = HashMap::from([("b1".to_string(), None), ("s2".to_string(), Some(0))]);
+        let clamped = clamp_to_onchain(
+            vec![
+                record("b1", "s1", 20),
+                record("b2", "s2", 20),
+                record("b2", "s3", 20),
+            ],
+            &onchain,
+        );
+        assert_eq!(amounts(&clamped), [("b2", "s3", 20)]);
+    }
+}
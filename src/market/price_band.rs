@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+use crate::model::MatchRecord;
+
+/// Keeps match prices within `band` (a fraction, e.g. `0.05` for 5%) of the
+/// average of the last `window` matched prices, so a pair of tiny crossing
+/// orders cannot print an outlying price. Nothing is held back until the
+/// window has filled up.
+#[derive(Debug, Clone)]
+pub struct PriceBand {
+    pub band: f64,
+    pub window: usize,
+    history: VecDeque<u128>,
+}
+
+impl PriceBand {
+    pub fn new(band: f64, window: usize) -> Self {
+        Self {
+            band,
+            window,
+            history: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Splits a cycle's matches into `(kept, outside)` against the average
+    /// from before the cycle, then adds the kept prices to the history.
+    /// Outlying prices are kept out of it so they cannot drag the band.
+    pub fn filter(&mut self, matches: Vec<MatchRecord>) -> (Vec<MatchRecord>, Vec<MatchRecord>) {
+        let (kept, outside): (Vec<_>, Vec<_>) = if self.window > 0
+            && self.history.len() >= self.window
+        {
+            let average = self.history.iter().map(|p| *p as f64).sum::<f64>() / self.window as f64;
+            let limit = average * self.band;
+            matches
+                .into_iter()
+                .partition(|record| (record.price as f64 - average).abs() <= limit)
+        } else {
+            (matches, Vec::new())
+        };
+
+        self.history.extend(kept.iter().map(|record| record.price));
+        while self.history.len() > self.window {
+            self.history.pop_front();
+        }
+        (kept, outside)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, price: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: format!("b{}", id),
+            sell_id: format!("s{}", id),
+            amount: 1,
+            price,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn ids(records: &[MatchRecord]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| record.buy_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn defers_an_off_band_pair() {
+        let mut band = PriceBand::new(0.05, 3);
+        let (kept, outside) =
+            band.filter(vec![record("1", 100), record("2", 100), record("3", 100)]);
+        assert_eq!((kept.len(), outside.len()), (3, 0));
+
+        // 5% of 100 either side: 95 and 105 pass, 94 and 106 do not.
+        let (kept, outside) = band.filter(vec![
+            record("4", 105),
+            record("5", 106),
+            record("6", 95),
+            record("7", 94),
+        ]);
+        assert_eq!(ids(&kept), ["b4", "b6"]);
+        assert_eq!(ids(&outside), ["b5", "b7"]);
+    }
+
+    #[test]
+    fn outliers_do_not_drag_the_average() {
+        let mut band = PriceBand::new(0.1, 2);
+        band.filter(vec![record("1", 100), record("2", 100)]);
+        for _ in 0..3 {
+            let (kept, outside) = band.filter(vec![record("x", 1_000)]);
+            assert!(kept.is_empty());
+            assert_eq!(ids(&outside), ["bx"]);
+        }
+        let (kept, _) = band.filter(vec![record("3", 110)]);
+        assert_eq!(ids(&kept), ["b3"]);
+    }
+
+    #[test]
+    fn nothing_is_deferred_until_the_window_fills() {
+        let mut band = PriceBand::new(0.01, 3);
+        let (kept, outside) = band.filter(vec![record("1", 100), record("2", 1_000)]);
+        assert_eq!((kept.len(), outside.len()), (2, 0));
+        let (_, outside) = band.filter(vec![record("3", 10)]);
+        assert!(outside.is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+
+/// Poison order detection: counts the failed chunks in a row each order was
+/// in, and keeps an order out of matching for the rest of the process's
+/// life once it has been in `threshold` of them. An order that keeps landing
+/// in the same chunk as a poison order is quarantined along with it.
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    pub threshold: u32,
+    failures: HashMap<String, u32>,
+    quarantined: HashSet<String>,
+}
+
+impl Quarantine {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            failures: HashMap::new(),
+            quarantined: HashSet::new(),
+        }
+    }
+
+    /// Counts a failed chunk against each of its orders. Returns the ids it
+    /// quarantined.
+    pub fn record_failures(&mut self, order_ids: &[String]) -> Vec<String> {
+        let mut newly_quarantined = Vec::new();
+        for id in order_ids {
+            let count = self.failures.entry(id.clone()).or_default();
+            *count += 1;
+            if *count >= self.threshold && self.quarantined.insert(id.clone()) {
+                newly_quarantined.push(id.clone());
+            }
+        }
+        newly_quarantined
+    }
+
+    /// Resets the count of orders whose chunk went through.
+    pub fn clear_failures(&mut self, order_ids: &[String]) {
+        for id in order_ids {
+            self.failures.remove(id);
+        }
+    }
+
+    pub fn contains(&self, order_id: &str) -> bool {
+        self.quarantined.contains(order_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn quarantines_an_order_after_repeated_failures() {
+        let mut quarantine = Quarantine::new(3);
+        assert!(quarantine
+            .record_failures(&ids(&["poison", "a"]))
+            .is_empty());
+        assert!(quarantine
+            .record_failures(&ids(&["poison", "b"]))
+            .is_empty());
+        assert_eq!(
+            quarantine.record_failures(&ids(&["poison", "c"])),
+            ["poison"]
+        );
+        assert!(quarantine.contains("poison"));
+        assert!(!quarantine.contains("a"));
+
+        // Quarantined once, not again on later failures.
+        assert!(quarantine.record_failures(&ids(&["poison"])).is_empty());
+    }
+
+    #[test]
+    fn a_chunk_that_goes_through_resets_the_count() {
+        let mut quarantine = Quarantine::new(2);
+        quarantine.record_failures(&ids(&["a", "b"]));
+        quarantine.clear_failures(&ids(&["a"]));
+        assert_eq!(quarantine.record_failures(&ids(&["a", "b"])), ["b"]);
+        assert_eq!(quarantine.record_failures(&ids(&["a"])), ["a"]);
+    }
+}
@@ -0,0 +1,81 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Exponential backoff between chunk retries. Each delay is scaled by a
+/// random factor so chunks that failed together do not retry in lockstep.
+pub struct Backoff {
+    pub base: Duration,
+    /// Fraction of the delay it may move by, clamped to `0.0..=1.0`.
+    pub jitter: f64,
+    rng: Mutex<StdRng>,
+}
+
+impl Backoff {
+    /// A fixed `seed` makes the sequence of delays reproducible.
+    pub fn new(base: Duration, jitter: f64, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            base,
+            jitter: jitter.clamp(0.0, 1.0),
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// `base * 2^attempt`, scaled by a factor in `[1 - jitter, 1 + jitter]`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base.saturating_mul(1 << attempt.min(16));
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        let factor = self
+            .rng
+            .lock()
+            .unwrap()
+            .gen_range(1.0 - self.jitter..=1.0 + self.jitter);
+        delay.mul_f64(factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn doubles_without_jitter_and_stops_growing() {
+        let backoff = Backoff::new(BASE, 0.0, None);
+        assert_eq!(backoff.delay(0), BASE);
+        assert_eq!(backoff.delay(3), BASE * 8);
+        assert_eq!(backoff.delay(16), backoff.delay(40));
+    }
+
+    #[test]
+    fn seeded_delays_stay_in_the_jitter_range_and_repeat() {
+        let backoff = Backoff::new(BASE, 0.25, Some(7));
+        let replay = Backoff::new(BASE, 0.25, Some(7));
+        for attempt in 0..10 {
+            let nominal = BASE * (1 << attempt);
+            let delay = backoff.delay(attempt);
+            assert!(
+                delay >= nominal.mul_f64(0.75) && delay <= nominal.mul_f64(1.25),
+                "attempt {}: {:?} outside 25% of {:?}",
+                attempt,
+                delay,
+                nominal
+            );
+            assert_eq!(delay, replay.delay(attempt));
+        }
+    }
+
+    #[test]
+    fn clamps_the_jitter() {
+        assert_eq!(Backoff::new(BASE, 3.0, Some(1)).jitter, 1.0);
+        assert_eq!(Backoff::new(BASE, -1.0, Some(1)).jitter, 0.0);
+    }
+}
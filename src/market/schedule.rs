@@ -0,0 +1,102 @@
+use chrono::{DateTime, NaiveTime, Utc};
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// A daily `[start, end)` window in UTC. A window whose end is not after its
+/// start wraps past midnight, so `22:00-02:00` covers the night.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Times of day at which the matcher is allowed to run a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub windows: Vec<TimeWindow>,
+}
+
+impl Schedule {
+    /// Whether `now` falls inside any of the windows. Takes the time as an
+    /// argument so callers decide which clock to read.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        self.windows.iter().any(|window| window.contains(time))
+    }
+}
+
+/// Parses comma-separated `HH:MM-HH:MM` windows, e.g. `09:00-17:00,22:00-02:00`.
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::StringParsingError(format!("invalid schedule {}", s));
+
+        let windows = s
+            .split(',')
+            .map(|window| {
+                let (start, end) = window.trim().split_once('-').ok_or_else(invalid)?;
+                let start =
+                    NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?;
+                let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?;
+                Ok(TimeWindow { start, end })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Schedule { windows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_windows_and_skips_times_outside_them() {
+        let schedule: Schedule = "09:00-17:00, 22:00-02:00".parse().unwrap();
+        assert_eq!(
+            schedule.windows[0],
+            TimeWindow {
+                start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            }
+        );
+
+        assert!(schedule.is_active(at(9, 0)));
+        assert!(schedule.is_active(at(12, 30)));
+        assert!(!schedule.is_active(at(17, 0)));
+        assert!(!schedule.is_active(at(18, 30)));
+        // The second window wraps past midnight.
+        assert!(schedule.is_active(at(23, 0)));
+        assert!(schedule.is_active(at(1, 59)));
+        assert!(!schedule.is_active(at(2, 0)));
+    }
+
+    #[test]
+    fn rejects_malformed_schedules() {
+        for s in ["", "09:00", "9-17", "09:00-25:00", "09:00-17:00,"] {
+            match s.parse::<Schedule>() {
+                Err(Error::StringParsingError(message)) => {
+                    assert_eq!(message, format!("invalid schedule {}", s))
+                }
+                other => panic!("{:?} parsed as {:?}", s, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+
+/// Whether a failed submission's error says the wallet could not sign the
+/// transaction, rather than the node or the contract rejecting it. The
+/// market SDK surfaces signing failures as plain fuels errors, so this goes
+/// by the message.
+pub fn is_signing_error(error: &str) -> bool {
+    let error = error.to_lowercase();
+    ["failed to sign", "signing", "signer", "signature"]
+        .iter()
+        .any(|marker| error.contains(marker))
+}
+
+/// Wallet addresses taken out of submission for the rest of a cycle after
+/// failing to sign, so one broken signer does not fail every chunk left.
+#[derive(Debug, Default)]
+pub struct PulledWallets(HashSet<String>);
+
+impl PulledWallets {
+    pub fn pull(&mut self, address: &str) {
+        self.0.insert(address.to_string());
+    }
+
+    /// The first of the `(address, wallet)` candidates, most preferred
+    /// first, that has not been pulled.
+    pub fn first_usable<T>(&self, candidates: impl IntoIterator<Item = (String, T)>) -> Option<T> {
+        candidates
+            .into_iter()
+            .find(|(address, _)| !self.0.contains(address))
+            .map(|(_, wallet)| wallet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_signing_errors_from_other_failures() {
+        assert!(is_signing_error(
+            "Failed to sign transaction: provider error"
+        ));
+        assert!(is_signing_error("invalid Signature for witness 0"));
+        assert!(!is_signing_error("Revert(123): NotEnoughBalance"));
+        assert!(!is_signing_error("connection refused"));
+    }
+
+    #[test]
+    fn a_wallet_that_failed_to_sign_is_passed_over() {
+        let mut pulled = PulledWallets::default();
+        let wallets = || vec![("primary".to_string(), 1), ("standby".to_string(), 2)];
+        assert_eq!(pulled.first_usable(wallets()), Some(1));
+
+        pulled.pull("primary");
+        assert_eq!(pulled.first_usable(wallets()), Some(2));
+
+        pulled.pull("standby");
+        assert_eq!(pulled.first_usable(wallets()), None);
+    }
+}
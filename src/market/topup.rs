@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+use fuels::accounts::wallet::WalletUnlocked;
+
+/// Refills a submission wallet's base-asset balance, e.g. from a treasury
+/// wallet. Called by the matcher when the balance falls under
+/// `GAS_BALANCE_FLOOR` before a cycle is submitted.
+#[async_trait]
+pub trait TopupProvider: Send + Sync {
+    async fn topup(&self, wallet: &WalletUnlocked, amount: u64);
+}
+
+/// Default provider: the low balance is logged by the matcher and nothing
+/// else happens.
+pub struct NoopTopup;
+
+#[async_trait]
+impl TopupProvider for NoopTopup {
+    async fn topup(&self, _wallet: &WalletUnlocked, _amount: u64) {}
+}
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+
+/// Dead-man's switch on cycle volume: flags a cycle whose matched amount is
+/// more than `multiple` times the average of the last `window` cycles that
+/// matched anything. Nothing is flagged until the window has filled up.
+#[derive(Debug, Clone)]
+pub struct VolumeGuard {
+    pub multiple: f64,
+    pub window: usize,
+    history: VecDeque<u128>,
+}
+
+impl VolumeGuard {
+    pub fn new(multiple: f64, window: usize) -> Self {
+        Self {
+            multiple,
+            window,
+            history: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Returns `true` if `volume` is anomalous. Anomalous volumes are kept
+    /// out of the history so one bad cycle cannot raise the baseline.
+    pub fn check(&mut self, volume: u128) -> bool {
+        if self.window > 0 && self.history.len() >= self.window {
+            let average = self.history.iter().map(|v| *v as f64).sum::<f64>() / self.window as f64;
+            if volume as f64 > average * self.multiple {
+                return true;
+            }
+        }
+
+        self.history.push_back(volume);
+        while self.history.len() > self.window {
+            self.history.pop_front();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_a_cycle_far_above_the_recent_average() {
+        let mut guard = VolumeGuard::new(3.0, 3);
+        // Nothing is flagged while the window fills.
+        assert!(!guard.check(10));
+        assert!(!guard.check(1_000));
+        assert!(!guard.check(10));
+
+        let mut guard = VolumeGuard::new(3.0, 3);
+        for volume in [10, 20, 30] {
+            assert!(!guard.check(volume));
+        }
+        assert!(!guard.check(60));
+        // The average is now (20 + 30 + 60) / 3, so 111 trips the switch.
+        assert!(guard.check(111));
+        assert!(!guard.check(110));
+    }
+
+    #[test]
+    fn anomalous_cycles_do_not_raise_the_baseline() {
+        let mut guard = VolumeGuard::new(2.0, 2);
+        assert!(!guard.check(10));
+        assert!(!guard.check(10));
+        for _ in 0..5 {
+            assert!(guard.check(u128::MAX));
+        }
+        assert!(!guard.check(20));
+    }
+
+    #[test]
+    fn an_empty_window_never_flags() {
+        let mut guard = VolumeGuard::new(1.0, 0);
+        assert!(!guard.check(0));
+        assert!(!guard.check(u128::MAX));
+    }
+}
@@ -0,0 +1,252 @@
+use async_trait::async_trait;
+use log::{error, warn};
+use serde::Serialize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::error::Error;
+use crate::market::matcher::MatchReport;
+use crate::metrics::Metrics;
+use crate::model::MatchRecord;
+
+/// One crossed match, as published to the export topic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MatchEvent {
+    /// The market contract id, which is also the message key.
+    pub market: String,
+    pub cycle_id: String,
+    #[serde(flatten)]
+    pub record: MatchRecord,
+    /// Whether the match was in a chunk that was submitted successfully.
+    pub submitted: bool,
+}
+
+/// Sends events to the broker.
+#[async_trait]
+pub trait MatchPublisher: Send + Sync {
+    async fn publish(&self, event: &MatchEvent) -> Result<(), Error>;
+}
+
+/// Publishes every match of a cycle without holding the cycle up.
+///
+/// Events are queued into a bounded buffer drained by a task of their own;
+/// when the broker falls behind and the buffer is full, new events are
+/// dropped and counted in `export_dropped_events`. Failed publishes are
+/// logged, counted in `export_failures` and not retried.
+pub struct MatchExporter {
+    market: String,
+    sender: mpsc::Sender<MatchEvent>,
+    metrics: Arc<Metrics>,
+}
+
+impl MatchExporter {
+    pub fn spawn(
+        market: String,
+        publisher: Arc<dyn MatchPublisher>,
+        buffer: usize,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<MatchEvent>(buffer.max(1));
+        let task_metrics = metrics.clone();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                if let Err(e) = publisher.publish(&event).await {
+                    task_metrics.export_failures.fetch_add(1, Ordering::Relaxed);
+                    error!(
+                        "Failed to export match {} x {} of cycle {}: {}",
+                        event.record.buy_id, event.record.sell_id, event.cycle_id, e
+                    );
+                }
+            }
+        });
+        Self {
+            market,
+            sender,
+            metrics,
+        }
+    }
+
+    /// Queues one event per match of the cycle. Never waits.
+    pub fn export(&self, report: &MatchReport) {
+        let mut dropped = 0;
+        for record in &report.matches {
+            let event = MatchEvent {
+                market: self.market.clone(),
+                cycle_id: report.cycle_id.clone(),
+                submitted: report.matched_ids.contains(&record.buy_id)
+                    && report.matched_ids.contains(&record.sell_id),
+                record: record.clone(),
+            };
+            match self.sender.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => dropped += 1,
+                Err(TrySendError::Closed(_)) => {
+                    error!(
+                        "Match export task is gone, not exporting cycle {}",
+                        report.cycle_id
+                    );
+                    return;
+                }
+            }
+        }
+        if dropped > 0 {
+            self.metrics
+                .export_dropped_events
+                .fetch_add(dropped, Ordering::Relaxed);
+            warn!(
+                "Export buffer full, dropped {} of {} match events of cycle {}",
+                dropped,
+                report.matches.len(),
+                report.cycle_id
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct MockPublisher {
+        published: Mutex<Vec<MatchEvent>>,
+        /// Buy ids whose events fail to publish.
+        failing: HashSet<String>,
+    }
+
+    #[async_trait]
+    impl MatchPublisher for MockPublisher {
+        async fn publish(&self, event: &MatchEvent) -> Result<(), Error> {
+            if self.failing.contains(&event.record.buy_id) {
+                return Err(Error::ExportError("broker unavailable".to_string()));
+            }
+            self.published.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    fn record(buy_id: &str, sell_id: &str, amount: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount,
+            price: 10,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn report(matches: Vec<MatchRecord>, matched_ids: &[&str]) -> MatchReport {
+        MatchReport {
+            cycle_id: "c1".to_string(),
+            total_amount: matches
+                .iter()
+                .map(|m| m.amount)
+                .fold(0, u128::saturating_add),
+            matches,
+            matched_ids: matched_ids.iter().map(|id| id.to_string()).collect(),
+            failed_chunks: 0,
+            deferred_chunks: 0,
+        }
+    }
+
+    /// Lets the export task drain the buffer.
+    async fn drained(publisher: &MockPublisher, expected: usize) -> Vec<MatchEvent> {
+        for _ in 0..100 {
+            if publisher.published.lock().unwrap().len() >= expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        publisher.published.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn publishes_every_match_keyed_by_market() {
+        let publisher = Arc::new(MockPublisher::default());
+        let metrics = Metrics::new();
+        let exporter = MatchExporter::spawn("0xmarket".to_string(), publisher.clone(), 16, metrics);
+
+        exporter.export(&report(
+            vec![record("b1", "s1", 5), record("b2", "s2", 7)],
+            &["b1", "s1"],
+        ));
+
+        let published = drained(&publisher, 2).await;
+        assert_eq!(
+            published,
+            vec![
+                MatchEvent {
+                    market: "0xmarket".to_string(),
+                    cycle_id: "c1".to_string(),
+                    record: record("b1", "s1", 5),
+                    submitted: true,
+                },
+                MatchEvent {
+                    market: "0xmarket".to_string(),
+                    cycle_id: "c1".to_string(),
+                    record: record("b2", "s2", 7),
+                    submitted: false,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_full_buffer_drops_events_instead_of_waiting() {
+        let publisher = Arc::new(MockPublisher::default());
+        let metrics = Metrics::new();
+        let exporter = MatchExporter::spawn(
+            "0xmarket".to_string(),
+            publisher.clone(),
+            1,
+            metrics.clone(),
+        );
+
+        // The export task cannot run before this returns, so the buffer of
+        // one takes the first event and the other two are dropped.
+        exporter.export(&report(
+            vec![
+                record("b1", "s1", 1),
+                record("b2", "s2", 1),
+                record("b3", "s3", 1),
+            ],
+            &[],
+        ));
+
+        assert_eq!(metrics.export_dropped_events.load(Ordering::Relaxed), 2);
+        let published = drained(&publisher, 1).await;
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].record.buy_id, "b1");
+    }
+
+    #[tokio::test]
+    async fn failed_publishes_are_counted_and_skipped() {
+        let publisher = Arc::new(MockPublisher {
+            failing: HashSet::from(["b1".to_string()]),
+            ..Default::default()
+        });
+        let metrics = Metrics::new();
+        let exporter = MatchExporter::spawn(
+            "0xmarket".to_string(),
+            publisher.clone(),
+            16,
+            metrics.clone(),
+        );
+
+        exporter.export(&report(
+            vec![record("b1", "s1", 1), record("b2", "s2", 1)],
+            &[],
+        ));
+
+        let published = drained(&publisher, 1).await;
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].record.buy_id, "b2");
+        assert_eq!(metrics.export_failures.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,41 @@
+use async_trait::async_trait;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::time::Duration;
+
+use super::exporter::{MatchEvent, MatchPublisher};
+use crate::error::Error;
+
+/// Publishes match events as JSON to a Kafka topic, keyed by market.
+pub struct KafkaPublisher {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaPublisher {
+    pub fn new(brokers: &str, topic: String) -> Result<Self, Error> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", "5000")
+            .create()
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+        Ok(Self { producer, topic })
+    }
+}
+
+#[async_trait]
+impl MatchPublisher for KafkaPublisher {
+    async fn publish(&self, event: &MatchEvent) -> Result<(), Error> {
+        let payload = serde_json::to_string(event)?;
+        let record = FutureRecord::to(&self.topic)
+            .key(&event.market)
+            .payload(&payload);
+        // Fail right away instead of waiting when librdkafka's own queue is
+        // full; the exporter's buffer already absorbs bursts.
+        self.producer
+            .send(record, Duration::ZERO)
+            .await
+            .map(|_| ())
+            .map_err(|(e, _)| Error::ExportError(e.to_string()))
+    }
+}
@@ -0,0 +1,14 @@
+pub mod exporter;
+pub mod kafka;
+
+pub use exporter::MatchExporter;
+pub use kafka::KafkaPublisher;
+
+/// Where each cycle's matches are published when `KAFKA_EXPORT` is set.
+#[derive(Debug, Clone)]
+pub struct KafkaExport {
+    pub brokers: String,
+    pub topic: String,
+    /// Events waiting to be published; past it new events are dropped.
+    pub buffer: usize,
+}
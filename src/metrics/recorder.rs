@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// Process-wide counters shared by the matcher and the web server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub cycles: AtomicU64,
+    pub book_hash: AtomicU64,
+    pub overlap_skipped_cycles: AtomicU64,
+    pub budget_exceeded_cycles: AtomicU64,
+    pub stale_cycles: AtomicU64,
+    pub feed_lag_skipped_cycles: AtomicU64,
+    pub unconfirmed_chunks: AtomicU64,
+    pub reorgs: AtomicU64,
+    pub signing_failures: AtomicU64,
+    pub book_invariant_violations: AtomicU64,
+    pub fill_discrepancies: AtomicU64,
+    pub quarantined_orders: AtomicU64,
+    pub export_dropped_events: AtomicU64,
+    pub export_failures: AtomicU64,
+    /// Running totals in raw contract units, stuck at `u64::MAX` once they
+    /// overflow.
+    pub maker_rebates: AtomicU64,
+    pub taker_fees: AtomicU64,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct MetricsSnapshot {
+    pub cycles: u64,
+    pub book_hash: String,
+    pub overlap_skipped_cycles: u64,
+    pub budget_exceeded_cycles: u64,
+    pub stale_cycles: u64,
+    pub feed_lag_skipped_cycles: u64,
+    pub unconfirmed_chunks: u64,
+    pub reorgs: u64,
+    pub signing_failures: u64,
+    pub book_invariant_violations: u64,
+    pub fill_discrepancies: u64,
+    pub quarantined_orders: u64,
+    pub export_dropped_events: u64,
+    pub export_failures: u64,
+    pub maker_rebates: u64,
+    pub taker_fees: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Adds `amount` to `counter`, saturating instead of wrapping.
+    pub fn saturating_add(counter: &AtomicU64, amount: u128) {
+        let amount = u64::try_from(amount).unwrap_or(u64::MAX);
+        let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+            Some(total.saturating_add(amount))
+        });
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            cycles: self.cycles.load(Ordering::Relaxed),
+            book_hash: format!("{:016x}", self.book_hash.load(Ordering::Relaxed)),
+            overlap_skipped_cycles: self.overlap_skipped_cycles.load(Ordering::Relaxed),
+            budget_exceeded_cycles: self.budget_exceeded_cycles.load(Ordering::Relaxed),
+            stale_cycles: self.stale_cycles.load(Ordering::Relaxed),
+            feed_lag_skipped_cycles: self.feed_lag_skipped_cycles.load(Ordering::Relaxed),
+            unconfirmed_chunks: self.unconfirmed_chunks.load(Ordering::Relaxed),
+            reorgs: self.reorgs.load(Ordering::Relaxed),
+            signing_failures: self.signing_failures.load(Ordering::Relaxed),
+            book_invariant_violations: self.book_invariant_violations.load(Ordering::Relaxed),
+            fill_discrepancies: self.fill_discrepancies.load(Ordering::Relaxed),
+            quarantined_orders: self.quarantined_orders.load(Ordering::Relaxed),
+            export_dropped_events: self.export_dropped_events.load(Ordering::Relaxed),
+            export_failures: self.export_failures.load(Ordering::Relaxed),
+            maker_rebates: self.maker_rebates.load(Ordering::Relaxed),
+            taker_fees: self.taker_fees.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+/// One crossed pair of orders.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MatchRecord {
+    pub buy_id: String,
+    pub sell_id: String,
+    pub amount: u128,
+    /// The sell's limit price, which the match settles at.
+    pub price: u128,
+    pub buy_tag: Option<String>,
+    pub sell_tag: Option<String>,
+}
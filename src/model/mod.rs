@@ -1,3 +1,7 @@
+pub mod match_record;
+pub mod order_event;
 pub mod spot_order;
 
+pub use match_record::MatchRecord;
+pub use order_event::OrderEvent;
 pub use spot_order::{OrderType, SpotOrder};
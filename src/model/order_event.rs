@@ -0,0 +1,18 @@
+use serde::Deserialize;
+
+use super::{OrderType, SpotOrder};
+
+/// A change to one order, from ingest sources that also see cancels.
+/// Externally tagged, e.g. `{"Cancel": {"id": "0x..", "price": 10,
+/// "order_type": "Buy"}}`.
+#[derive(Debug, Clone, Deserialize)]
+pub enum OrderEvent {
+    /// Books the order, handled like a re-sent order if its id is booked.
+    Open(SpotOrder),
+    /// Takes the order out of the book.
+    Cancel {
+        id: String,
+        price: u128,
+        order_type: OrderType,
+    },
+}
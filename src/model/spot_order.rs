@@ -2,7 +2,9 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, JsonSchema, Serialize, Deserialize)]
+use crate::error::Error;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, JsonSchema, Serialize, Deserialize)]
 pub enum OrderType {
     Buy,
     Sell,
@@ -15,8 +17,17 @@ pub struct SpotOrder {
     pub asset: String,
     pub amount: u128,
     pub price: u128,
+    /// Unix seconds the order was created; 0 when the feed sent none.
+    #[serde(default)]
     pub timestamp: u64,
     pub order_type: OrderType,
+    /// Client or strategy the order belongs to, for attributing fills.
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Unix milliseconds at which this matcher first booked the order, 0
+    /// until it is booked. Kept when the order is re-ingested.
+    #[serde(default)]
+    pub received_at: i64,
 }
 
 impl PartialEq for SpotOrder {
@@ -37,41 +48,83 @@ impl PartialOrd for SpotOrder {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct SpotOrderIndexer {
-    pub id: String,
-    pub user: String,
-    pub asset: String,
-    pub amount: String,
-    pub price: String,
-    pub timestamp: String,
-    pub order_type: OrderType,
-}
-
 impl SpotOrder {
-    pub fn from_indexer(
-        intermediate: SpotOrderIndexer,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let amount = intermediate.amount.parse::<u128>()?;
-        let price = intermediate.price.parse::<u128>()?;
-        let timestamp =
-            chrono::DateTime::parse_from_rfc3339(&intermediate.timestamp)?.timestamp() as u64;
+    /// Builds an order from one element of the indexer's `Order` array.
+    /// Amount and price may be decimal strings or numbers, the timestamp an
+    /// RFC 3339 string or unix seconds (0 when absent), and the owner is
+    /// read from `user` or `owner`. Errors name the offending field.
+    pub fn from_indexer_json(value: &Value) -> Result<Self, Error> {
+        let field = |name: &str| {
+            value.get(name).filter(|v| !v.is_null()).ok_or_else(|| {
+                Error::StringParsingError(format!("indexer order: missing {}", name))
+            })
+        };
+        let invalid = |name: &str, v: &Value| {
+            Error::StringParsingError(format!("indexer order: invalid {} {}", name, v))
+        };
+        let string = |name: &str| -> Result<String, Error> {
+            let v = field(name)?;
+            v.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| invalid(name, v))
+        };
+        let number = |name: &str| -> Result<u128, Error> {
+            let v = field(name)?;
+            match v {
+                Value::String(s) => s.parse().ok(),
+                Value::Number(n) => n.as_u64().map(u128::from),
+                _ => None,
+            }
+            .ok_or_else(|| invalid(name, v))
+        };
+
+        let owner = match value.get("user").filter(|v| !v.is_null()) {
+            Some(_) => string("user")?,
+            None => string("owner")?,
+        };
+
+        let order_type = field("order_type")?;
+        let order_type = match order_type.as_str() {
+            Some("Buy") => OrderType::Buy,
+            Some("Sell") => OrderType::Sell,
+            _ => return Err(invalid("order_type", order_type)),
+        };
+
+        let timestamp = match value.get("timestamp") {
+            None | Some(Value::Null) => 0,
+            Some(timestamp) => match timestamp {
+                Value::String(s) => chrono::DateTime::parse_from_rfc3339(s)
+                    .ok()
+                    .map(|t| t.timestamp() as u64),
+                Value::Number(n) => n.as_u64(),
+                _ => None,
+            }
+            .ok_or_else(|| invalid("timestamp", timestamp))?,
+        };
+
+        let tag = match value.get("tag") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(tag)) => Some(tag.clone()),
+            Some(other) => return Err(invalid("tag", other)),
+        };
 
         Ok(SpotOrder {
-            id: intermediate.id,
-            user: intermediate.user,
-            asset: intermediate.asset,
-            amount,
-            price,
+            id: string("id")?,
+            user: owner,
+            asset: string("asset")?,
+            amount: number("amount")?,
+            price: number("price")?,
             timestamp,
-            order_type: intermediate.order_type,
+            order_type,
+            tag,
+            received_at: 0,
         })
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct OrderPayload {
-    pub Order: Vec<SpotOrderIndexer>,
+    pub Order: Vec<Value>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -85,3 +138,111 @@ pub struct WebSocketResponse {
     pub id: Option<String>,
     pub payload: Option<DataPayload>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn indexer_order() -> Value {
+        json!({
+            "id": "0x01",
+            "user": "0xa11ce",
+            "asset": "0xb7c",
+            "amount": "150",
+            "price": "70000",
+            "timestamp": "2024-05-01T12:00:00Z",
+            "order_type": "Buy",
+        })
+    }
+
+    fn with(field: &str, value: Value) -> Value {
+        let mut order = indexer_order();
+        order[field] = value;
+        order
+    }
+
+    fn without(field: &str) -> Value {
+        let mut order = indexer_order();
+        order.as_object_mut().unwrap().remove(field);
+        order
+    }
+
+    fn error_of(order: Value) -> String {
+        match SpotOrder::from_indexer_json(&order) {
+            Err(Error::StringParsingError(message)) => message,
+            other => panic!("expected a parsing error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_an_indexer_order() {
+        let order = SpotOrder::from_indexer_json(&indexer_order()).unwrap();
+        assert_eq!(order.id, "0x01");
+        assert_eq!(order.user, "0xa11ce");
+        assert_eq!(order.asset, "0xb7c");
+        assert_eq!(order.amount, 150);
+        assert_eq!(order.price, 70000);
+        assert_eq!(order.timestamp, 1714564800);
+        assert_eq!(order.order_type, OrderType::Buy);
+        assert_eq!(order.tag, None);
+        assert_eq!(order.received_at, 0);
+    }
+
+    #[test]
+    fn accepts_numbers_an_owner_field_and_no_timestamp() {
+        let mut order = without("user");
+        order["owner"] = json!("0xb0b");
+        order["amount"] = json!(150);
+        order["price"] = json!(70000);
+        order["order_type"] = json!("Sell");
+        order["tag"] = json!("mm-1");
+        order.as_object_mut().unwrap().remove("timestamp");
+
+        let order = SpotOrder::from_indexer_json(&order).unwrap();
+        assert_eq!(order.user, "0xb0b");
+        assert_eq!((order.amount, order.price), (150, 70000));
+        assert_eq!(order.timestamp, 0);
+        assert_eq!(order.order_type, OrderType::Sell);
+        assert_eq!(order.tag.as_deref(), Some("mm-1"));
+
+        let unix = SpotOrder::from_indexer_json(&with("timestamp", json!(1714564800))).unwrap();
+        assert_eq!(unix.timestamp, 1714564800);
+    }
+
+    #[test]
+    fn names_the_missing_field() {
+        for field in ["id", "price", "amount", "order_type", "asset"] {
+            assert_eq!(
+                error_of(without(field)),
+                format!("indexer order: missing {}", field)
+            );
+        }
+        assert_eq!(error_of(without("user")), "indexer order: missing owner");
+        assert_eq!(
+            error_of(with("price", Value::Null)),
+            "indexer order: missing price"
+        );
+    }
+
+    #[test]
+    fn names_the_invalid_field() {
+        let cases = [
+            ("id", json!(7)),
+            ("user", json!(false)),
+            ("asset", json!(["0xb7c"])),
+            ("amount", json!("1.5")),
+            ("price", json!(-5)),
+            ("order_type", json!("Hold")),
+            ("timestamp", json!("yesterday")),
+            ("tag", json!(3)),
+        ];
+        for (field, value) in cases {
+            let message = error_of(with(field, value.clone()));
+            assert_eq!(
+                message,
+                format!("indexer order: invalid {} {}", field, value)
+            );
+        }
+    }
+}
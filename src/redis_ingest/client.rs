@@ -0,0 +1,231 @@
+use log::{error, info, warn};
+use redis::aio::MultiplexedConnection;
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use redis::AsyncCommands;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::error::Error;
+use crate::market::retry::Backoff;
+use crate::model::{OrderEvent, SpotOrder};
+
+/// Doublings of the reconnect delay before it stops growing, 32s from the
+/// 500ms base.
+const MAX_RECONNECT_ATTEMPT: u32 = 6;
+
+/// Consumes order events from a Redis stream through a consumer group.
+///
+/// Each entry carries an [`OrderEvent`] as JSON in its `event` field, or a
+/// bare order to open in its `order` field. Entries are acknowledged only
+/// after the event has been handed to the manager channel, so on restart the
+/// consumer first replays its pending (delivered but not acked) entries and
+/// then continues from the group's last delivered id.
+///
+/// Entries that cannot be parsed are moved to `dead_letter_stream` with the
+/// reason and acked there; without one they stay pending for inspection. The
+/// consumer reconnects with backoff whenever Redis fails.
+#[derive(Debug, Clone)]
+pub struct RedisIngest {
+    pub url: String,
+    pub stream: String,
+    pub group: String,
+    pub consumer: String,
+    pub dead_letter_stream: Option<String>,
+}
+
+impl RedisIngest {
+    /// Runs until the manager channel closes; Redis errors only reconnect.
+    pub async fn run(&self, sender: mpsc::Sender<OrderEvent>) -> Result<(), Error> {
+        let backoff = Backoff::new(Duration::from_millis(500), 0.5, None);
+        let mut attempt = 0;
+        loop {
+            match self.consume(&sender, &mut attempt).await {
+                Err(Error::RedisError(e)) => {
+                    let delay = backoff.delay(attempt);
+                    warn!("Redis ingest failed: {}, reconnecting in {:?}", e, delay);
+                    attempt = (attempt + 1).min(MAX_RECONNECT_ATTEMPT);
+                    tokio::time::sleep(delay).await;
+                }
+                other => return other,
+            }
+        }
+    }
+
+    /// Connects and forwards entries until an error. `attempt` is reset once
+    /// a read succeeds.
+    async fn consume(
+        &self,
+        sender: &mpsc::Sender<OrderEvent>,
+        attempt: &mut u32,
+    ) -> Result<(), Error> {
+        let client = redis::Client::open(self.url.as_str())?;
+        let mut con = client.get_multiplexed_async_connection().await?;
+
+        let created: redis::RedisResult<()> = con
+            .xgroup_create_mkstream(&self.stream, &self.group, "$")
+            .await;
+        if let Err(e) = created {
+            if e.code() != Some("BUSYGROUP") {
+                return Err(e.into());
+            }
+        }
+
+        info!(
+            "Redis ingest consuming {} as {}/{}",
+            self.stream, self.group, self.consumer
+        );
+
+        // "0" re-reads entries delivered to this consumer but never acked,
+        // ">" asks for entries no consumer in the group has seen yet. While
+        // replaying, the cursor moves past what was read, so entries left
+        // pending on purpose are not read again.
+        let mut cursor = "0".to_string();
+        loop {
+            let opts = StreamReadOptions::default()
+                .group(&self.group, &self.consumer)
+                .count(100)
+                .block(5000);
+            let reply: StreamReadReply = con
+                .xread_options(&[&self.stream], &[&cursor], &opts)
+                .await?;
+            *attempt = 0;
+
+            let mut last_id = None;
+            for key in reply.keys {
+                for entry in key.ids {
+                    match parse_entry(&entry) {
+                        Ok(event) => {
+                            if sender.send(event).await.is_err() {
+                                return Err(Error::ProcessMessagePayloadError(
+                                    "order channel closed".to_string(),
+                                ));
+                            }
+                            let _: i64 = con.xack(&self.stream, &self.group, &[&entry.id]).await?;
+                        }
+                        Err(reason) => self.reject(&mut con, &entry, &reason).await?,
+                    }
+                    last_id = Some(entry.id);
+                }
+            }
+
+            if cursor != ">" {
+                cursor = last_id.unwrap_or_else(|| ">".to_string());
+            }
+        }
+    }
+
+    /// Dead-letters an entry that could not be parsed, or leaves it pending
+    /// when there is no dead-letter stream.
+    async fn reject(
+        &self,
+        con: &mut MultiplexedConnection,
+        entry: &StreamId,
+        reason: &str,
+    ) -> Result<(), Error> {
+        let Some(dead_letter_stream) = &self.dead_letter_stream else {
+            warn!("Leaving stream entry {} pending: {}", entry.id, reason);
+            return Ok(());
+        };
+
+        error!(
+            "Moving stream entry {} to {}: {}",
+            entry.id, dead_letter_stream, reason
+        );
+        let mut fields: Vec<(String, String)> = entry
+            .map
+            .keys()
+            .filter_map(|field| Some((field.clone(), entry.get::<String>(field)?)))
+            .collect();
+        fields.sort();
+        fields.push(("source_id".to_string(), entry.id.clone()));
+        fields.push(("error".to_string(), reason.to_string()));
+        let _: String = con.xadd(dead_letter_stream, "*", &fields).await?;
+        let _: i64 = con.xack(&self.stream, &self.group, &[&entry.id]).await?;
+        Ok(())
+    }
+}
+
+/// Reads the event of an entry from its `event` field, falling back to an
+/// order to open in its `order` field.
+fn parse_entry(entry: &StreamId) -> Result<OrderEvent, String> {
+    if let Some(json) = entry.get::<String>("event") {
+        return serde_json::from_str(&json).map_err(|e| format!("bad `event`: {}", e));
+    }
+    match entry.get::<String>("order") {
+        Some(json) => serde_json::from_str::<SpotOrder>(&json)
+            .map(OrderEvent::Open)
+            .map_err(|e| format!("bad `order`: {}", e)),
+        None => Err("no `event` or `order` field".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::OrderType;
+    use std::collections::HashMap;
+
+    fn entry(fields: &[(&str, &str)]) -> StreamId {
+        StreamId {
+            id: "1-0".to_string(),
+            map: fields
+                .iter()
+                .map(|(field, value)| {
+                    (
+                        field.to_string(),
+                        redis::Value::Data(value.as_bytes().to_vec()),
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    const ORDER: &str =
+        r#"{"id":"b1","user":"alice","asset":"eth","amount":5,"price":10,"order_type":"Buy"}"#;
+
+    #[test]
+    fn parses_open_and_cancel_events() {
+        let open = format!(r#"{{"Open":{}}}"#, ORDER);
+        match parse_entry(&entry(&[("event", &open)])) {
+            Ok(OrderEvent::Open(order)) => {
+                assert_eq!((order.id.as_str(), order.amount), ("b1", 5))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let cancel = r#"{"Cancel":{"id":"b1","price":10,"order_type":"Sell"}}"#;
+        match parse_entry(&entry(&[("event", cancel)])) {
+            Ok(OrderEvent::Cancel {
+                id,
+                price,
+                order_type,
+            }) => assert_eq!(
+                (id.as_str(), price, order_type),
+                ("b1", 10, OrderType::Sell)
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_bare_order_opens_it() {
+        match parse_entry(&entry(&[("order", ORDER)])) {
+            Ok(OrderEvent::Open(order)) => assert_eq!(order.id, "b1"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_entries_say_why() {
+        assert_eq!(
+            parse_entry(&entry(&[("other", "x")])).unwrap_err(),
+            "no `event` or `order` field"
+        );
+        assert!(parse_entry(&entry(&[("event", "{}")]))
+            .unwrap_err()
+            .starts_with("bad `event`"));
+        assert!(parse_entry(&entry(&[("order", "{")]))
+            .unwrap_err()
+            .starts_with("bad `order`"));
+    }
+}
@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::error::Error;
+
+/// Matched volume and number of submitted transactions logged since `since`.
+pub async fn fetch_volume(db_pool: &PgPool, since: DateTime<Utc>) -> Result<(u128, i64), Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(SUM(total_amount::NUMERIC), 0) AS "volume!",
+            COUNT(*) AS "transactions!"
+        FROM transaction_stats
+        WHERE created_at >= to_timestamp($1)
+        "#,
+        since.timestamp() as f64,
+    )
+    .fetch_one(db_pool)
+    .await?;
+
+    let volume = row
+        .volume
+        .to_string()
+        .parse::<u128>()
+        .map_err(|_| Error::StringParsingError(format!("volume {}", row.volume)))?;
+
+    Ok((volume, row.transactions))
+}
@@ -0,0 +1,169 @@
+use opentelemetry::global;
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, Resource};
+
+use crate::error::Error;
+use crate::model::MatchRecord;
+
+/// Installs an OTLP exporter sending to `endpoint` as the global tracer
+/// provider. Until then the global tracer is a no-op, so cycles pay nothing
+/// for their spans when tracing is not configured.
+pub fn init(endpoint: &str) -> Result<(), Error> {
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::Config::default().with_resource(Resource::new(vec![
+            KeyValue::new("service.name", "spark-matcher"),
+        ])))
+        .install_batch(runtime::Tokio)
+        .map_err(|e| Error::TelemetryError(e.to_string()))?;
+    global::set_tracer_provider(provider);
+    Ok(())
+}
+
+/// Flushes spans still waiting in the batch exporter.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// The span of one `match_orders` cycle, parent of its chunk spans. Dropped
+/// without `finish`, e.g. on a cycle with nothing to submit, it still ends.
+pub struct CycleSpan {
+    cx: Context,
+}
+
+impl CycleSpan {
+    pub fn start<T>(tracer: &T, cycle_id: &str) -> Self
+    where
+        T: Tracer,
+        T::Span: Send + Sync + 'static,
+    {
+        let mut span = tracer.start("match_orders");
+        span.set_attribute(KeyValue::new("cycle_id", cycle_id.to_string()));
+        Self {
+            cx: Context::current_with_span(span),
+        }
+    }
+
+    pub fn chunk<T: Tracer>(
+        &self,
+        tracer: &T,
+        index: usize,
+        chunk: &[MatchRecord],
+    ) -> ChunkSpan<T::Span> {
+        let mut span = tracer.start_with_context("submit_chunk", &self.cx);
+        span.set_attribute(KeyValue::new("chunk", index as i64));
+        span.set_attribute(KeyValue::new("matches_len", chunk.len() as i64));
+        span.set_attribute(KeyValue::new(
+            "total_amount",
+            total_amount(chunk).to_string(),
+        ));
+        ChunkSpan(span)
+    }
+
+    pub fn finish(self, matches: &[MatchRecord], tx_ids: &[String]) {
+        let span = self.cx.span();
+        span.set_attribute(KeyValue::new("matches_len", matches.len() as i64));
+        span.set_attribute(KeyValue::new(
+            "total_amount",
+            total_amount(matches).to_string(),
+        ));
+        span.set_attribute(KeyValue::new("tx_ids", tx_ids.join(",")));
+        span.end();
+    }
+}
+
+/// The span of one chunk submission. Dropped without an outcome, e.g. when
+/// its confirmation is uncertain, it ends without a `tx_id` or an error.
+pub struct ChunkSpan<S: Span>(S);
+
+impl<S: Span> ChunkSpan<S> {
+    pub fn submitted(mut self, tx_id: &str) {
+        self.0
+            .set_attribute(KeyValue::new("tx_id", tx_id.to_string()));
+        self.0.end();
+    }
+
+    pub fn failed(mut self, reason: &str) {
+        self.0.set_status(Status::error(reason.to_string()));
+        self.0.end();
+    }
+}
+
+fn total_amount(matches: &[MatchRecord]) -> u128 {
+    matches
+        .iter()
+        .map(|m| m.amount)
+        .fold(0, u128::saturating_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::export::trace::SpanData;
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+    use opentelemetry_sdk::trace::TracerProvider;
+
+    fn record(buy_id: &str, sell_id: &str, amount: u128) -> MatchRecord {
+        MatchRecord {
+            buy_id: buy_id.to_string(),
+            sell_id: sell_id.to_string(),
+            amount,
+            price: 10,
+            buy_tag: None,
+            sell_tag: None,
+        }
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<String> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.as_str().into_owned())
+    }
+
+    #[test]
+    fn a_cycle_spans_its_chunks() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = provider.tracer("spark-matcher");
+
+        let matches = [
+            record("b1", "s1", 5),
+            record("b1", "s2", 7),
+            record("b2", "s3", 3),
+        ];
+        let cycle = CycleSpan::start(&tracer, "c1");
+        cycle.chunk(&tracer, 0, &matches[..2]).submitted("abc");
+        cycle.chunk(&tracer, 1, &matches[2..]).failed("Revert(42)");
+        cycle.finish(&matches, &["abc".to_string()]);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["submit_chunk", "submit_chunk", "match_orders"]);
+        let (first, second, cycle) = (&spans[0], &spans[1], &spans[2]);
+
+        assert_eq!(attribute(cycle, "cycle_id").as_deref(), Some("c1"));
+        assert_eq!(attribute(cycle, "matches_len").as_deref(), Some("3"));
+        assert_eq!(attribute(cycle, "total_amount").as_deref(), Some("15"));
+        assert_eq!(attribute(cycle, "tx_ids").as_deref(), Some("abc"));
+
+        for chunk in [first, second] {
+            assert_eq!(chunk.parent_span_id, cycle.span_context.span_id());
+        }
+        assert_eq!(attribute(first, "matches_len").as_deref(), Some("2"));
+        assert_eq!(attribute(first, "total_amount").as_deref(), Some("12"));
+        assert_eq!(attribute(first, "tx_id").as_deref(), Some("abc"));
+        assert_eq!(attribute(second, "tx_id"), None);
+        assert_eq!(second.status, Status::error("Revert(42)"));
+    }
+}
@@ -1,7 +1,8 @@
+use std::cmp::Reverse;
 use std::sync::Arc;
 
 use rocket::serde::json::Json;
-use rocket::{self, get, Route, State};
+use rocket::{self, get, post, Route, State};
 use rocket_okapi::settings::UrlObject;
 use rocket_okapi::swagger_ui::SwaggerUIConfig;
 use rocket_okapi::{openapi, openapi_get_routes, JsonSchema};
@@ -10,8 +11,14 @@ use sqlx::types::BigDecimal;
 use sqlx::PgPool;
 use tokio::sync::RwLock;
 
+use crate::management::depth::L2Snapshot;
 use crate::management::manager::OrderManager;
+use crate::market::crossing::{explain_matches, CrossingStep, MatchSettings};
+use crate::market::matcher::PauseSwitch;
+use crate::metrics::recorder::MetricsSnapshot;
+use crate::metrics::Metrics;
 use crate::model::SpotOrder;
+use crate::stats::fetch_volume;
 
 #[derive(Serialize, JsonSchema)]
 pub struct StatsResponse {
@@ -25,6 +32,13 @@ pub struct StatsResponse {
     pub avg_post_time_ms: String,
 }
 
+#[derive(Serialize, JsonSchema)]
+pub struct VolumeResponse {
+    pub since: String,
+    pub volume: String,
+    pub transactions: i64,
+}
+
 #[derive(Serialize, JsonSchema)]
 pub struct OrdersResponse {
     pub orders: Vec<SpotOrder>,
@@ -36,6 +50,18 @@ pub struct CurrentOrdersResponse {
     pub sell_orders: Vec<SpotOrder>,
 }
 
+#[derive(Serialize, JsonSchema)]
+pub struct ExplainResponse {
+    pub matches: usize,
+    pub total_amount: String,
+    pub steps: Vec<CrossingStep>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PauseResponse {
+    pub paused: bool,
+}
+
 #[openapi]
 #[get("/stats")]
 async fn get_stats(db: &State<PgPool>) -> Json<StatsResponse> {
@@ -78,6 +104,23 @@ async fn get_stats(db: &State<PgPool>) -> Json<StatsResponse> {
     })
 }
 
+/// Matched volume since an RFC 3339 timestamp, e.g.
+/// `/stats/volume?since=2024-07-01T00:00:00Z`.
+#[openapi]
+#[get("/stats/volume?<since>")]
+async fn get_volume(db: &State<PgPool>, since: String) -> Option<Json<VolumeResponse>> {
+    let since_time = chrono::DateTime::parse_from_rfc3339(&since)
+        .ok()?
+        .with_timezone(&chrono::Utc);
+    let (volume, transactions) = fetch_volume(db, since_time).await.ok()?;
+
+    Some(Json(VolumeResponse {
+        since,
+        volume: volume.to_string(),
+        transactions,
+    }))
+}
+
 #[openapi]
 #[get("/orders/buy")]
 async fn get_buy_orders(manager: &State<Arc<OrderManager>>) -> Json<OrdersResponse> {
@@ -104,8 +147,85 @@ async fn get_all_orders(manager: &State<Arc<OrderManager>>) -> Json<CurrentOrder
     })
 }
 
+/// Aggregated price levels, best first, `depth` per side (default 20).
+#[openapi]
+#[get("/book/l2?<depth>")]
+async fn get_l2_book(manager: &State<Arc<OrderManager>>, depth: Option<usize>) -> Json<L2Snapshot> {
+    Json(manager.l2_snapshot(depth.unwrap_or(20)).await)
+}
+
+#[openapi]
+#[get("/metrics")]
+async fn get_metrics(metrics: &State<Arc<Metrics>>) -> Json<MetricsSnapshot> {
+    Json(metrics.snapshot())
+}
+
+/// Dry-runs the crossing loop over the current book with the matcher's
+/// settings and returns every decision it made. Nothing is submitted.
+#[openapi]
+#[get("/explain")]
+async fn get_explain(
+    manager: &State<Arc<OrderManager>>,
+    settings: &State<MatchSettings>,
+) -> Json<ExplainResponse> {
+    let (buy_orders, sell_orders) = manager.get_all_orders().await;
+    let (outcome, steps) = explain_matches(
+        buy_orders.into_iter().collect(),
+        sell_orders.into_iter().map(Reverse).collect(),
+        settings,
+    );
+    Json(ExplainResponse {
+        matches: outcome.matches.len(),
+        total_amount: outcome.total_amount.to_string(),
+        steps,
+    })
+}
+
+/// Stops cycles from matching; the book keeps following the feed.
+#[openapi]
+#[post("/pause")]
+async fn pause_matching(paused: &State<PauseSwitch>) -> Json<PauseResponse> {
+    paused.pause();
+    Json(PauseResponse { paused: true })
+}
+
+#[openapi]
+#[post("/resume")]
+async fn resume_matching(paused: &State<PauseSwitch>) -> Json<PauseResponse> {
+    paused.resume();
+    Json(PauseResponse { paused: false })
+}
+
+/// Every route but the database-backed `/stats` ones, which come from
+/// `get_stats_routes`. Both sets share one OpenAPI document.
 pub fn get_routes() -> Vec<Route> {
-    openapi_get_routes![get_stats, get_buy_orders, get_sell_orders, get_all_orders,]
+    all_routes()
+        .into_iter()
+        .filter(|route| !is_stats_route(route))
+        .collect()
+}
+
+pub fn get_stats_routes() -> Vec<Route> {
+    all_routes().into_iter().filter(is_stats_route).collect()
+}
+
+fn is_stats_route(route: &Route) -> bool {
+    route.uri.path().starts_with("/stats")
+}
+
+fn all_routes() -> Vec<Route> {
+    openapi_get_routes![
+        get_stats,
+        get_volume,
+        get_buy_orders,
+        get_sell_orders,
+        get_all_orders,
+        get_l2_book,
+        get_metrics,
+        get_explain,
+        pause_matching,
+        resume_matching,
+    ]
 }
 
 pub fn get_docs() -> SwaggerUIConfig {
@@ -4,13 +4,32 @@ use rocket::{Build, Rocket};
 use rocket_okapi::swagger_ui::make_swagger_ui;
 use sqlx::PgPool;
 
-use super::routes::{get_docs, get_routes};
+use super::routes::{get_docs, get_routes, get_stats_routes};
 use crate::management::manager::OrderManager;
+use crate::market::crossing::MatchSettings;
+use crate::market::matcher::PauseSwitch;
+use crate::metrics::Metrics;
 
-pub fn rocket(db_pool: PgPool, order_manager: Arc<OrderManager>) -> Rocket<Build> {
-    rocket::build()
-        .manage(db_pool)
+/// Without a `db_pool` the `/stats` routes are left unmounted; they still
+/// appear in the OpenAPI document.
+pub fn rocket(
+    db_pool: Option<PgPool>,
+    order_manager: Arc<OrderManager>,
+    metrics: Arc<Metrics>,
+    match_settings: MatchSettings,
+    paused: PauseSwitch,
+) -> Rocket<Build> {
+    let rocket = match db_pool {
+        Some(db_pool) => rocket::build()
+            .manage(db_pool)
+            .mount("/", get_stats_routes()),
+        None => rocket::build(),
+    };
+    rocket
         .manage(order_manager)
+        .manage(metrics)
+        .manage(match_settings)
+        .manage(paused)
         .mount("/", get_routes())
         .mount("/swagger", make_swagger_ui(&get_docs()))
 }
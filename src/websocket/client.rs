@@ -1,9 +1,10 @@
 use futures_util::{SinkExt, StreamExt};
 use log::{error, info};
 use serde_json::Value;
+use std::sync::Arc;
 use tokio::{
     net::TcpStream,
-    sync::mpsc,
+    sync::{mpsc, Notify},
     time::{self, Duration, Instant},
 };
 use tokio_tungstenite::{
@@ -13,24 +14,29 @@ use url::Url;
 
 use crate::{
     api::subscription::format_graphql_subscription,
-    model::{
-        spot_order::{SpotOrderIndexer, WebSocketResponse},
-        OrderType, SpotOrder,
-    },
+    model::{spot_order::WebSocketResponse, OrderEvent, OrderType, SpotOrder},
 };
 
 pub struct WebSocketClient {
     pub url: Url,
+    pub fetch_order_limit: u32,
+    /// Reconnects and subscribes again when woken, so the indexer sends the
+    /// open orders anew.
+    pub resync: Arc<Notify>,
 }
 
 impl WebSocketClient {
-    pub fn new(url: Url) -> Self {
-        WebSocketClient { url }
+    pub fn new(url: Url, fetch_order_limit: u32, resync: Arc<Notify>) -> Self {
+        WebSocketClient {
+            url,
+            fetch_order_limit,
+            resync,
+        }
     }
 
     pub async fn connect(
         &self,
-        sender: mpsc::Sender<SpotOrder>,
+        sender: mpsc::Sender<OrderEvent>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         loop {
             let mut initialized = false;
@@ -55,7 +61,17 @@ impl WebSocketClient {
                 .await?;
 
             let mut last_data_time = Instant::now();
-            while let Some(message) = ws_stream.next().await {
+            loop {
+                let message = tokio::select! {
+                    message = ws_stream.next() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = self.resync.notified() => {
+                        info!("Book resync requested, resubscribing to orders");
+                        break;
+                    }
+                };
                 if Instant::now().duration_since(last_data_time) > Duration::from_secs(60) {
                     error!("No data messages received for the last 60 seconds, reconnecting...");
                     break;
@@ -82,10 +98,15 @@ impl WebSocketClient {
                                 }
                                 "data" => {
                                     if let Some(payload) = response.payload {
-                                        for order_indexer in payload.data.Order {
-                                            let spot_order =
-                                                SpotOrder::from_indexer(order_indexer)?;
-                                            sender.send(spot_order).await?;
+                                        for order in payload.data.Order {
+                                            match SpotOrder::from_indexer_json(&order) {
+                                                Ok(spot_order) => {
+                                                    sender
+                                                        .send(OrderEvent::Open(spot_order))
+                                                        .await?
+                                                }
+                                                Err(e) => error!("Skipping {}: {}", order, e),
+                                            }
                                         }
                                         last_data_time = Instant::now();
                                     }
@@ -134,7 +155,7 @@ impl WebSocketClient {
         order_type: OrderType,
         client: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let subscription_query = format_graphql_subscription(order_type);
+        let subscription_query = format_graphql_subscription(order_type, self.fetch_order_limit);
         let start_msg = serde_json::json!({
             "id": format!("{}", order_type as u8),
             "type": "start",
@@ -0,0 +1,156 @@
+//! End to end run against an in-process fuel-core node: deploys a market,
+//! opens a crossing buy and sell on it, runs one matching cycle and checks
+//! that both orders were filled on chain.
+//!
+//! The node comes from the `fuel-core-lib` feature of `fuels`, so nothing
+//! has to be started by hand. It is slow, so it only runs when asked:
+//!
+//! ```text
+//! cargo test --test local_node -- --ignored
+//! ```
+
+use fuels::accounts::wallet::WalletUnlocked;
+use fuels::accounts::{Account, ViewOnlyAccount};
+use fuels::test_helpers::{launch_custom_provider_and_get_wallets, AssetConfig, WalletsConfig};
+use fuels::types::transaction::TxPolicies;
+use fuels::types::{AssetId, Bits256};
+use spark_market_sdk::MarketContract;
+use std::collections::HashMap;
+
+use spark_matcher::config::Config;
+use spark_matcher::management::manager::OrderManager;
+use spark_matcher::market::SparkMatcher;
+use spark_matcher::metrics::Metrics;
+use spark_matcher::model::{OrderType, SpotOrder};
+
+/// Mnemonic of the matcher's wallet, which the test funds for gas.
+const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+const DECIMALS: u32 = 9;
+const AMOUNT: u64 = 1_000_000_000;
+const PRICE: u64 = 2_000_000_000;
+
+fn order_id(id: &Bits256) -> String {
+    format!("0x{}", hex::encode(id.0))
+}
+
+fn spot_order(id: &Bits256, user: &WalletUnlocked, order_type: OrderType) -> SpotOrder {
+    SpotOrder {
+        id: order_id(id),
+        user: user.address().hash().to_string(),
+        asset: String::new(),
+        amount: AMOUNT as u128,
+        price: PRICE as u128,
+        timestamp: 0,
+        order_type,
+        tag: None,
+        received_at: 0,
+    }
+}
+
+#[tokio::test]
+#[ignore = "starts a fuel-core node, run with --ignored"]
+async fn matches_a_crossing_pair_on_chain() {
+    let base_asset = AssetId::new([1; 32]);
+    let quote_asset = AssetId::new([2; 32]);
+    let coins = |id| AssetConfig {
+        id,
+        num_coins: 1,
+        coin_amount: 1_000_000_000_000,
+    };
+    let wallets = launch_custom_provider_and_get_wallets(
+        WalletsConfig::new_multiple_assets(
+            3,
+            vec![
+                coins(AssetId::zeroed()),
+                coins(base_asset),
+                coins(quote_asset),
+            ],
+        ),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let (owner, buyer, seller) = (&wallets[0], &wallets[1], &wallets[2]);
+    let provider = owner.try_provider().unwrap().clone();
+
+    let market = MarketContract::deploy(
+        base_asset,
+        DECIMALS,
+        quote_asset,
+        DECIMALS,
+        owner.clone(),
+        DECIMALS,
+        0,
+    )
+    .await
+    .unwrap();
+
+    let selling = market.with_account(seller).await.unwrap();
+    selling.deposit(AMOUNT, base_asset).await.unwrap();
+    let sell_id = selling
+        .open_order(AMOUNT, spark_market_sdk::OrderType::Sell, PRICE)
+        .await
+        .unwrap()
+        .value;
+
+    let buying = market.with_account(buyer).await.unwrap();
+    buying.deposit(AMOUNT * 10, quote_asset).await.unwrap();
+    let buy_id = buying
+        .open_order(AMOUNT, spark_market_sdk::OrderType::Buy, PRICE)
+        .await
+        .unwrap()
+        .value;
+
+    let matcher_wallet =
+        WalletUnlocked::new_from_mnemonic_phrase(MNEMONIC, Some(provider.clone())).unwrap();
+    owner
+        .transfer(
+            matcher_wallet.address(),
+            1_000_000_000,
+            AssetId::zeroed(),
+            TxPolicies::default(),
+        )
+        .await
+        .unwrap();
+
+    let vars: HashMap<String, String> = [
+        ("WEBSOCKET_URL", "ws://127.0.0.1:1/v1/graphql".to_string()),
+        ("MNEMONIC", MNEMONIC.to_string()),
+        ("CONTRACT_ID", format!("0x{}", market.contract_id().hash())),
+        ("PROVIDER_URL", provider.url().to_string()),
+        ("LOG_SINK", "Noop".to_string()),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value))
+    .collect();
+    let config = Config::from_map(&vars).unwrap();
+
+    let order_manager = OrderManager::new();
+    assert!(
+        order_manager
+            .add_order(spot_order(&buy_id, buyer, OrderType::Buy))
+            .await
+    );
+    assert!(
+        order_manager
+            .add_order(spot_order(&sell_id, seller, OrderType::Sell))
+            .await
+    );
+
+    let matcher = SparkMatcher::new(order_manager.clone(), Metrics::new(), &config)
+        .await
+        .unwrap();
+    matcher.match_orders().await.unwrap();
+
+    for id in [buy_id, sell_id] {
+        assert!(
+            market.order(id).await.unwrap().value.is_none(),
+            "order {} is still open on chain",
+            order_id(&id)
+        );
+    }
+    assert!(order_manager.get_all_buy_orders().await.is_empty());
+    assert!(order_manager.get_all_sell_orders().await.is_empty());
+}